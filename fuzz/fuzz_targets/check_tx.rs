@@ -0,0 +1,36 @@
+#![no_main]
+
+use lib::blockchain::application::{Settings, SnarkVMApp};
+use libfuzzer_sys::fuzz_target;
+use std::sync::Mutex;
+use tendermint_abci::Application;
+use tendermint_proto::abci::{CheckTxType, RequestCheckTx, RequestInitChain};
+
+/// The application is opened once and shared by all the runs, since opening its storage is expensive.
+static APP: Mutex<Option<SnarkVMApp>> = Mutex::new(None);
+
+fn open_app() -> SnarkVMApp {
+    let home = std::env::temp_dir().join("snarkvm_abci_fuzz");
+    let _ = std::fs::remove_dir_all(&home);
+    let app = SnarkVMApp::new(&home, Settings::default());
+    app.init_chain(RequestInitChain {
+        chain_id: "fuzz-chain".to_string(),
+        app_state_bytes: br#"{"records": [], "validators": []}"#.to_vec(),
+        ..Default::default()
+    });
+    app
+}
+
+// any bytes sent to the rpc endpoint reach check tx, both when they are first received and when the mempool is
+// rechecked after a block, so they should be rejected without panicking the node
+fuzz_target!(|data: &[u8]| {
+    let mut app = APP.lock().unwrap();
+    let app = app.get_or_insert_with(open_app);
+    for check_type in [CheckTxType::New, CheckTxType::Recheck] {
+        let response = app.check_tx(RequestCheckTx {
+            tx: data.to_vec(),
+            r#type: check_type as i32,
+        });
+        assert_ne!(0, response.code, "arbitrary bytes were accepted");
+    }
+});
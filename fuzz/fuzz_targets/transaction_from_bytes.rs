@@ -0,0 +1,15 @@
+#![no_main]
+
+use lib::transaction::Transaction;
+use libfuzzer_sys::fuzz_target;
+
+// decoding arbitrary bytes, as received by the abci app, should fail gracefully instead of panicking,
+// and decoded transactions should be safe to inspect before they are verified
+fuzz_target!(|data: &[u8]| {
+    if let Ok(transaction) = Transaction::from_bytes(data) {
+        let _ = transaction.verify();
+        let _ = transaction.fees();
+        let _ = transaction.record_serial_numbers();
+        let _ = transaction.stake_updates();
+    }
+});
@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use fs2::FileExt;
+use serde::Deserialize;
+use std::fs::File;
+use std::path::Path;
+
+/// Name of the optional config file in the application home directory.
+const CONFIG_FILE: &str = "abci.toml";
+
+/// Name of the file locked while a process is using the application home directory.
+const LOCK_FILE: &str = "abci.lock";
+
+/// Settings read from the config file of the application home directory.
+/// Every setting is optional, and the ones passed as command line arguments take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub read_buf_size: Option<usize>,
+    pub snapshot_interval: Option<u64>,
+    pub snapshot_keep: Option<usize>,
+    pub retain_blocks: Option<u64>,
+    pub prune_records: Option<bool>,
+    pub verification_cache_size: Option<usize>,
+    pub tendermint_url: Option<String>,
+}
+
+impl Config {
+    /// Load the config file from the given home directory, or return an empty config if there's none.
+    pub fn load(home: &Path) -> Result<Self> {
+        let path = home.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        toml::from_str(&contents).map_err(|e| anyhow!("invalid config file {path:?}: {e}"))
+    }
+}
+
+/// Take an exclusive lock on the given home directory, so two processes can't open the same application state.
+/// The lock is held until the returned file is dropped, and released by the OS if the process dies.
+pub fn lock_home(home: &Path) -> Result<File> {
+    std::fs::create_dir_all(home)?;
+    let path = home.join(LOCK_FILE);
+    let file = File::create(&path)?;
+    file.try_lock_exclusive()
+        .map_err(|_| anyhow!("{home:?} is already in use by another process (see {path:?})"))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn load_config() {
+        let home = TempDir::new().unwrap();
+        let config = Config::load(home.path()).unwrap();
+        assert!(config.port.is_none());
+
+        std::fs::write(home.path().join(CONFIG_FILE), "port = 26758\n").unwrap();
+        let config = Config::load(home.path()).unwrap();
+        assert_eq!(Some(26758), config.port);
+        assert!(config.host.is_none());
+
+        std::fs::write(home.path().join(CONFIG_FILE), "unknown = 1\n").unwrap();
+        assert!(Config::load(home.path()).is_err());
+    }
+
+    #[test]
+    fn home_is_locked() {
+        let home = TempDir::new().unwrap();
+        let lock = lock_home(home.path()).unwrap();
+        assert!(lock_home(home.path()).is_err());
+
+        drop(lock);
+        assert!(lock_home(home.path()).is_ok());
+    }
+}
@@ -5,7 +5,7 @@ use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::Result;
 use clap::Parser;
-use lib::{validator, vm};
+use lib::{fees::FeeSchedule, validator, vm};
 
 /// Takes a list of node directories and updates the genesis files on each of them
 /// to include records to assign default credits to each validator and a mapping
@@ -23,6 +23,30 @@ pub struct Cli {
     /// The amount of gates to assign to each validator
     #[clap(long, default_value = "1000")]
     amount: u64,
+
+    /// Minimum fee, in gates, paid by every transaction
+    #[clap(long, default_value = "0")]
+    base_fee: u64,
+
+    /// Minimum fee per byte of the serialized transaction
+    #[clap(long, default_value = "0")]
+    fee_per_byte: u64,
+
+    /// Minimum fee per transition of the transaction
+    #[clap(long, default_value = "0")]
+    fee_per_transition: u64,
+
+    /// Minimum fee per function of a deployed program
+    #[clap(long, default_value = "0")]
+    fee_per_function: u64,
+
+    /// Minimum fee per instruction of the deployed or executed functions
+    #[clap(long, default_value = "0")]
+    fee_per_instruction: u64,
+
+    /// Require transactions to reveal their input records, so validators can check that they exist in the ledger
+    #[clap(long)]
+    reveal_input_records: bool,
 }
 
 fn main() -> Result<()> {
@@ -84,8 +108,20 @@ fn main() -> Result<()> {
 
     // update the genesis JSON with the calculated app state
     let genesis_state = validator::GenesisState {
+        chain_id: genesis["chain_id"]
+            .as_str()
+            .expect("couldn't extract chain id from genesis")
+            .to_string(),
         records: genesis_records,
         validators,
+        fees: FeeSchedule {
+            base: cli.base_fee,
+            per_byte: cli.fee_per_byte,
+            per_transition: cli.fee_per_transition,
+            per_function: cli.fee_per_function,
+            per_instruction: cli.fee_per_instruction,
+        },
+        reveal_input_records: cli.reveal_input_records,
     };
     genesis.as_object_mut().unwrap().insert(
         "app_state".to_string(),
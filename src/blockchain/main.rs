@@ -1,30 +1,64 @@
 //! In-memory key/value store application for Tendermint.
 
-use application::SnarkVMApp;
 use clap::Parser;
+use config::Config;
+use lib::blockchain::application::{Settings, SnarkVMApp};
+use std::path::PathBuf;
 use tendermint_abci::ServerBuilder;
 use tracing_subscriber::{filter::LevelFilter, util::SubscriberInitExt};
 
-mod application;
-mod program_store;
-mod record_store;
-mod validator_set;
+mod config;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Cli {
-    /// Bind the TCP server to this host.
-    #[clap(long, default_value = "127.0.0.1")]
-    host: String,
+    /// Directory where the application state is stored. It can also contain an abci.toml file
+    /// with default values for the rest of the options.
+    #[clap(long, env = "ABCI_HOME", default_value = ".")]
+    home: PathBuf,
 
-    /// Bind the TCP server to this port.
-    #[clap(short, long, default_value = "26658")]
-    port: u16,
+    /// Bind the TCP server to this host [default: 127.0.0.1].
+    #[clap(long)]
+    host: Option<String>,
+
+    /// Bind the TCP server to this port [default: 26658].
+    #[clap(short, long)]
+    port: Option<u16>,
 
     /// The default server read buffer size, in bytes, for each incoming client
-    /// connection.
-    #[clap(short, long, default_value = "1048576")]
-    read_buf_size: usize,
+    /// connection [default: 1048576].
+    #[clap(short, long)]
+    read_buf_size: Option<usize>,
+
+    /// Take a snapshot of the application state every this many blocks, to be served to nodes
+    /// joining the network through state sync. Zero disables snapshots [default: 0].
+    #[clap(long)]
+    snapshot_interval: Option<u64>,
+
+    /// Amount of recent snapshots to keep [default: 2].
+    #[clap(long)]
+    snapshot_keep: Option<usize>,
+
+    /// Amount of recent blocks to keep. Older blocks are pruned from the tendermint block store
+    /// and can't be rolled back. Zero keeps all blocks [default: 0].
+    #[clap(long)]
+    retain_blocks: Option<u64>,
+
+    /// Also drop the ciphertexts of the records added before the retained blocks. Their
+    /// commitments are kept, so they can still be spent, but clients can't fetch them from this node.
+    #[clap(long)]
+    prune_records: bool,
+
+    /// Amount of transitions with verified proofs to remember, so they aren't verified again
+    /// when delivered after being checked for the mempool. Zero disables the cache [default: 10000].
+    #[clap(long)]
+    verification_cache_size: Option<usize>,
+
+    /// URL of the tendermint RPC of the node (e.g. http://127.0.0.1:26657), used to fetch the transactions of
+    /// each block when it begins and verify all their proofs in parallel in the background. If not set, the
+    /// proofs of each transaction are verified when it's delivered.
+    #[clap(long)]
+    tendermint_url: Option<String>,
 
     /// Increase output logging verbosity to DEBUG level.
     #[clap(short, long)]
@@ -33,6 +67,11 @@ struct Cli {
     /// Suppress all output logging (overrides --verbose).
     #[clap(short, long)]
     quiet: bool,
+
+    /// Roll back the application state to the given height and exit, to recover from a bad block.
+    /// Tendermint replays the following blocks from its block store on the next start.
+    #[clap(long)]
+    rollback_to: Option<i64>,
 }
 
 fn main() {
@@ -58,9 +97,59 @@ fn main() {
 
     subscriber.init();
 
-    let app = SnarkVMApp::new();
-    let server = ServerBuilder::new(cli.read_buf_size)
-        .bind(format!("{}:{}", cli.host, cli.port), app)
+    let config = Config::load(&cli.home).expect("could not load the config file");
+    let host = cli
+        .host
+        .or(config.host)
+        .unwrap_or_else(|| "127.0.0.1".to_string());
+    let port = cli.port.or(config.port).unwrap_or(26658);
+    let read_buf_size = cli
+        .read_buf_size
+        .or(config.read_buf_size)
+        .unwrap_or(1048576);
+    let settings = Settings {
+        snapshot_interval: cli
+            .snapshot_interval
+            .or(config.snapshot_interval)
+            .unwrap_or(0),
+        snapshot_keep: cli.snapshot_keep.or(config.snapshot_keep).unwrap_or(2),
+        retain_blocks: cli.retain_blocks.or(config.retain_blocks).unwrap_or(0),
+        prune_records: cli.prune_records || config.prune_records.unwrap_or(false),
+        verification_cache_size: cli
+            .verification_cache_size
+            .or(config.verification_cache_size)
+            .unwrap_or(10000),
+        tendermint_url: cli
+            .tendermint_url
+            .or(config.tendermint_url)
+            .filter(|url| !url.is_empty()),
+    };
+
+    // a node restoring the oldest snapshot needs to fetch the blocks after it from its peers
+    if settings.retain_blocks > 0
+        && settings.snapshot_interval > 0
+        && settings.retain_blocks < settings.snapshot_interval * settings.snapshot_keep as u64
+    {
+        tracing::warn!(
+            "retain_blocks ({}) is lower than snapshot_interval ({}) * snapshot_keep ({}), \
+             nodes restoring the oldest snapshots won't be able to fetch the blocks after them from this one",
+            settings.retain_blocks,
+            settings.snapshot_interval,
+            settings.snapshot_keep
+        );
+    }
+
+    // held until the process exits
+    let _lock = config::lock_home(&cli.home).expect("could not lock the home directory");
+
+    if let Some(height) = cli.rollback_to {
+        SnarkVMApp::rollback(&cli.home, height).expect("failed to roll back the application state");
+        return;
+    }
+
+    let app = SnarkVMApp::new(&cli.home, settings);
+    let server = ServerBuilder::new(read_buf_size)
+        .bind(format!("{host}:{port}"), app)
         .unwrap();
 
     server.listen().unwrap();
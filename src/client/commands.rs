@@ -1,17 +1,20 @@
+use crate::tendermint::TransactionStatus;
+use crate::wallet::Wallet;
 use crate::{account, tendermint};
 use anyhow::{anyhow, bail, Result};
 use clap::Parser;
 use itertools::Itertools;
+use lib::events;
 use lib::program_file::ProgramFile;
 use lib::query::AbciQuery;
 use lib::transaction::Transaction;
 use lib::vm::{self, ProgramID};
-use log::debug;
 use serde_json::json;
 use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
 use std::str::FromStr;
+use tendermint_rpc::query::Query;
 
 #[derive(Debug, Parser)]
 pub enum Command {
@@ -21,6 +24,8 @@ pub enum Command {
     Credits(Credits),
     #[clap(subcommand)]
     Program(Program),
+    #[clap(subcommand)]
+    Tx(Tx),
     #[clap(name = "get")]
     Get(Get),
 }
@@ -33,6 +38,8 @@ pub enum Account {
     Records,
     /// Fetches the unspent records owned by the given account and calculates the final credits balance.
     Balance,
+    /// Fetches the records added to the ledger since the last sync, and updates the local wallet.
+    Sync,
 }
 
 #[derive(Debug, Parser)]
@@ -51,6 +58,9 @@ pub enum Credits {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
     /// Split input record by amount
     Split {
@@ -64,6 +74,9 @@ pub enum Credits {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
     /// Combine two records into one
     Combine {
@@ -77,6 +90,9 @@ pub enum Credits {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
     /// Take credits out from a credits record and stake them as a blockchain validator. This will execute a program and output a
     /// stake record that can be later used to reclaim the staked credits.
@@ -96,6 +112,9 @@ pub enum Credits {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
     /// Take credits out of a stake record, reducing the voting power of the validator.
     Unstake {
@@ -111,6 +130,9 @@ pub enum Credits {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
 }
 
@@ -128,6 +150,9 @@ pub enum Program {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
     },
     /// Runs locally and sends an execution transaction to the blockchain, returning the Transaction ID
     Execute {
@@ -146,6 +171,9 @@ pub enum Program {
         /// The record to use to subtract the fee amount. If omitted, the record with most gates in the account is used.
         #[clap(long, value_parser=parse_input_record)]
         fee_record: Option<vm::Value>,
+        /// Amount of blocks after the latest one in which the transaction can be included. If omitted, it doesn't expire.
+        #[clap(long, value_parser=clap::value_parser!(u64).range(1..))]
+        expires_in: Option<u64>,
         /// Run the input code locally, generating the execution proof but without sending it over to the blockchain. Displays execution and decrypted records.
         #[clap(long, short, default_value_t = false)]
         dry_run: bool,
@@ -158,6 +186,32 @@ pub enum Program {
     },
 }
 
+/// Commands to follow transactions sent to the blockchain.
+#[derive(Debug, Parser)]
+pub enum Tx {
+    /// Reports whether a transaction is pending in the mempool, committed, along with its block height and index and
+    /// the records it added and spent, or failed, along with the error code and message.
+    Status {
+        /// Transaction ID to report the status of
+        #[clap(value_parser)]
+        transaction_id: String,
+    },
+    /// Searches the committed transactions that added or spent the given records or deployed the given program,
+    /// returning their ids along with their block height and index. At least one of the options is required, and
+    /// transactions need to match all of them.
+    Search {
+        /// Commitment of a record added by the transaction.
+        #[clap(long)]
+        commitment: Option<vm::Field>,
+        /// Serial number of a record spent by the transaction.
+        #[clap(long)]
+        serial_number: Option<vm::Field>,
+        /// Id of the program deployed by the transaction.
+        #[clap(long)]
+        program: Option<ProgramID>,
+    },
+}
+
 /// Return the status of a Transaction: Type, whether it is committed to the ledger, and the program name.
 /// In the case of execution transactions, it also outputs the function's inputs and outputs.
 #[derive(Debug, Parser)]
@@ -178,13 +232,15 @@ impl Command {
             let path = credentials.save()?;
 
             json!({"path": path, "account": credentials})
+        } else if let Command::Tx(command) = self {
+            run_tx_command(command, &url).await?
         } else {
             let credentials =
                 account::Credentials::load().map_err(|_| anyhow!("credentials not found"))?;
 
             match self {
-                Command::Account(Account::New) => {
-                    bail!("this shouldn't be reachable, the account new is a special case handled elsewhere")
+                Command::Account(Account::New) | Command::Tx(_) => {
+                    bail!("this shouldn't be reachable, the account new and tx commands are special cases handled elsewhere")
                 }
                 Command::Account(Account::Balance) => {
                     let balance = get_records(&credentials, &url)
@@ -208,15 +264,35 @@ impl Command {
                         .collect();
                     json!(&records)
                 }
+                Command::Account(Account::Sync) => {
+                    let wallet = sync_wallet(&credentials, &url).await?;
+                    json!({
+                        "height": wallet.height,
+                        "unspent_records": wallet.unspent_records().count()
+                    })
+                }
                 Command::Program(Program::Deploy {
                     path,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let fee = choose_fee_record(&credentials, &url, &fee, &fee_record, &[]).await?;
-                    let transaction =
-                        Transaction::deployment(&path, &credentials.private_key, fee)?;
-                    let transaction_serialized = bincode::serialize(&transaction).unwrap();
+                    let state_root = tendermint::get_state_root(&url).await?;
+                    let chain_id = tendermint::get_chain_id(&url).await?;
+                    let expires_at_height = expiry_height(&url, expires_in).await?;
+                    let program = vm::generate_program(&fs::read_to_string(&path)?)?;
+                    let imports = get_imports(&url, &program).await?;
+                    let transaction = Transaction::deployment(
+                        &path,
+                        &imports,
+                        &credentials.private_key,
+                        fee,
+                        state_root,
+                        &chain_id,
+                        expires_at_height,
+                    )?;
+                    let transaction_serialized = transaction.to_bytes()?;
                     tendermint::broadcast(transaction_serialized, &url).await?;
                     json!(transaction)
                 }
@@ -226,6 +302,7 @@ impl Command {
                     inputs,
                     fee,
                     fee_record,
+                    expires_in,
                     dry_run,
                 }) => {
                     let fee =
@@ -234,19 +311,27 @@ impl Command {
                         Some(program) => program,
                         None => bail!("Could not find program {}", program),
                     };
+                    let imports = get_imports(&url, &program).await?;
+                    let state_root = tendermint::get_state_root(&url).await?;
+                    let chain_id = tendermint::get_chain_id(&url).await?;
+                    let expires_at_height = expiry_height(&url, expires_in).await?;
                     let transaction = Transaction::execution(
                         program,
+                        &imports,
                         function,
                         &inputs,
                         &credentials.private_key,
                         fee,
+                        state_root,
+                        &chain_id,
+                        expires_at_height,
                     )?;
 
                     let mut transaction_json = json!(transaction);
                     if !dry_run {
                         let mut transaction_json = json!(transaction);
                         if !dry_run {
-                            let transaction_serialized = bincode::serialize(&transaction).unwrap();
+                            let transaction_serialized = transaction.to_bytes()?;
                             tendermint::broadcast(transaction_serialized, &url).await?;
                         } else {
                             let records = Self::decrypt_records(&transaction, credentials);
@@ -283,34 +368,61 @@ impl Command {
                     amount,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let inputs = [
                         input_record.clone(),
                         recipient_address.clone(),
                         vm::u64_to_value(amount),
                     ];
-                    run_credits_command(&credentials, &url, "transfer", &inputs, &fee, &fee_record)
-                        .await?
+                    run_credits_command(
+                        &credentials,
+                        &url,
+                        "transfer",
+                        &inputs,
+                        &fee,
+                        &fee_record,
+                        expires_in,
+                    )
+                    .await?
                 }
                 Command::Credits(Credits::Combine {
                     first_record,
                     second_record,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let inputs = [first_record.clone(), second_record.clone()];
-                    run_credits_command(&credentials, &url, "combine", &inputs, &fee, &fee_record)
-                        .await?
+                    run_credits_command(
+                        &credentials,
+                        &url,
+                        "combine",
+                        &inputs,
+                        &fee,
+                        &fee_record,
+                        expires_in,
+                    )
+                    .await?
                 }
                 Command::Credits(Credits::Split {
                     input_record,
                     amount,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let inputs = [input_record.clone(), vm::u64_to_value(amount)];
-                    run_credits_command(&credentials, &url, "split", &inputs, &fee, &fee_record)
-                        .await?
+                    run_credits_command(
+                        &credentials,
+                        &url,
+                        "split",
+                        &inputs,
+                        &fee,
+                        &fee_record,
+                        expires_in,
+                    )
+                    .await?
                 }
                 Command::Credits(Credits::Stake {
                     amount,
@@ -318,6 +430,7 @@ impl Command {
                     validator,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let (validator_higher, validator_lower) =
                         Transaction::validator_address_as_numbers(&base64::decode(validator)?)?;
@@ -329,25 +442,42 @@ impl Command {
                         vm::u128_to_value(validator_lower),
                     ];
 
-                    run_credits_command(&credentials, &url, "stake", &inputs, &fee, &fee_record)
-                        .await?
+                    run_credits_command(
+                        &credentials,
+                        &url,
+                        "stake",
+                        &inputs,
+                        &fee,
+                        &fee_record,
+                        expires_in,
+                    )
+                    .await?
                 }
                 Command::Credits(Credits::Unstake {
                     amount,
                     record,
                     fee,
                     fee_record,
+                    expires_in,
                 }) => {
                     let inputs = [record.clone(), vm::u64_to_value(amount)];
-                    run_credits_command(&credentials, &url, "unstake", &inputs, &fee, &fee_record)
-                        .await?
+                    run_credits_command(
+                        &credentials,
+                        &url,
+                        "unstake",
+                        &inputs,
+                        &fee,
+                        &fee_record,
+                        expires_in,
+                    )
+                    .await?
                 }
                 Command::Get(Get {
                     transaction_id,
                     decrypt,
                 }) => {
                     let transaction = tendermint::get_transaction(&transaction_id, &url).await?;
-                    let transaction: Transaction = bincode::deserialize(&transaction)?;
+                    let transaction = Transaction::from_bytes(&transaction)?;
 
                     if !decrypt {
                         json!(transaction)
@@ -386,15 +516,126 @@ async fn run_credits_command(
     inputs: &[vm::Value],
     fee_amount: &Option<u64>,
     fee_record: &Option<vm::Value>,
+    expires_in: Option<u64>,
 ) -> Result<serde_json::Value> {
     let fee = choose_fee_record(credentials, url, fee_amount, fee_record, inputs).await?;
-    let transaction =
-        Transaction::credits_execution(function, inputs, &credentials.private_key, fee)?;
-    let transaction_serialized = bincode::serialize(&transaction).unwrap();
+    let state_root = tendermint::get_state_root(url).await?;
+    let chain_id = tendermint::get_chain_id(url).await?;
+    let expires_at_height = expiry_height(url, expires_in).await?;
+    let transaction = Transaction::credits_execution(
+        function,
+        inputs,
+        &credentials.private_key,
+        fee,
+        state_root,
+        &chain_id,
+        expires_at_height,
+    )?;
+    let transaction_serialized = transaction.to_bytes()?;
     tendermint::broadcast(transaction_serialized, url).await?;
     Ok(json!(transaction))
 }
 
+/// Return the last height a transaction can be included in, if it should expire after the given amount of blocks.
+async fn expiry_height(url: &str, expires_in: Option<u64>) -> Result<Option<u64>> {
+    match expires_in {
+        Some(blocks) => Ok(Some(tendermint::get_height(url).await? + blocks)),
+        None => Ok(None),
+    }
+}
+
+/// Run the commands that only read from the blockchain, without requiring an account.
+async fn run_tx_command(command: Tx, url: &str) -> Result<serde_json::Value> {
+    match command {
+        Tx::Status { transaction_id } => transaction_status(&transaction_id, url).await,
+        Tx::Search {
+            commitment,
+            serial_number,
+            program,
+        } => {
+            let conditions = [
+                (
+                    events::COMMITMENT,
+                    commitment.map(|value| value.to_string()),
+                ),
+                (
+                    events::SERIAL_NUMBER,
+                    serial_number.map(|value| value.to_string()),
+                ),
+                (
+                    events::PROGRAM_DEPLOYED,
+                    program.map(|value| value.to_string()),
+                ),
+            ];
+            let query = conditions
+                .into_iter()
+                .filter_map(|(key, value)| value.map(|value| (key.query_key(), value)))
+                .fold(None, |query: Option<Query>, (key, value)| match query {
+                    Some(query) => Some(query.and_eq(key, value)),
+                    None => Some(Query::eq(key, value)),
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "at least one of --commitment, --serial-number or --program is required"
+                    )
+                })?;
+
+            let transactions: Vec<serde_json::Value> = tendermint::search_transactions(query, url)
+                .await?
+                .iter()
+                .map(|result| {
+                    json!({
+                        // the transaction id is the sha256 hash of its bytes, like the tendermint hash
+                        "id": result.hash.to_string().to_lowercase(),
+                        "height": result.height.value(),
+                        "index": result.index
+                    })
+                })
+                .collect();
+            Ok(json!(transactions))
+        }
+    }
+}
+
+/// Report the status of the transaction, with the records added and spent by it if it was committed.
+async fn transaction_status(transaction_id: &str, url: &str) -> Result<serde_json::Value> {
+    let status = match tendermint::get_transaction_status(transaction_id, url).await? {
+        TransactionStatus::Pending => json!({ "status": "pending" }),
+        TransactionStatus::Committed {
+            height,
+            index,
+            transaction,
+        } => {
+            let transaction = Transaction::from_bytes(&transaction)?;
+            let commitments: Vec<vm::Field> = transaction
+                .output_records()
+                .into_iter()
+                .map(|(commitment, _)| commitment)
+                .collect();
+            json!({
+                "status": "committed",
+                "height": height,
+                "index": index,
+                "commitments": commitments,
+                "serial_numbers": transaction.record_serial_numbers()
+            })
+        }
+        TransactionStatus::Failed {
+            height,
+            index,
+            error,
+        } => json!({
+            "status": "failed",
+            "height": height,
+            "index": index,
+            "error": error.message(),
+            "code": error.code()
+        }),
+        TransactionStatus::Unknown => json!({ "status": "unknown" }),
+    };
+    Ok(status)
+}
+
 /// Extends the snarkvm's default argument parsing to support using record ciphertexts as record inputs
 fn parse_input_value(input: &str) -> Result<vm::Value> {
     // try parsing an encrypted record string
@@ -425,33 +666,30 @@ pub fn parse_input_record(input: &str) -> Result<vm::Value> {
         .map(vm::Value::Record)
 }
 
-/// Retrieves all records from the blockchain, and only those that are correctly decrypted
-/// (i.e, are owned by the passed credentials) and have not been spent are returned
+/// Syncs the local wallet of the passed credentials with the ledger, fetching only the records
+/// added since the last sync.
+async fn sync_wallet(credentials: &account::Credentials, url: &str) -> Result<Wallet> {
+    let chain_id = tendermint::get_chain_id(url).await?;
+    let mut wallet = Wallet::load(&chain_id, &credentials.address);
+    wallet.sync(credentials, url).await?;
+    Ok(wallet)
+}
+
+/// Returns the records owned by the passed credentials that have not been spent, as known by the
+/// local wallet after syncing it.
 async fn get_records(
     credentials: &account::Credentials,
     url: &str,
 ) -> Result<Vec<(vm::Field, vm::EncryptedRecord, vm::Record)>> {
-    let get_records_response = tendermint::query(AbciQuery::GetRecords.into(), url).await?;
-    let get_spent_records_response =
-        tendermint::query(AbciQuery::GetSpentSerialNumbers.into(), url).await?;
-
-    let records: Vec<(vm::Field, vm::EncryptedRecord)> =
-        bincode::deserialize(&get_records_response)?;
-    let spent_records: HashSet<vm::Field> = bincode::deserialize(&get_spent_records_response)?;
-
-    debug!("Records: {:?}", records);
-    let records = records
-        .into_iter()
-        .filter_map(|(commitment, ciphertext)| {
-            ciphertext
-                .decrypt(&credentials.view_key)
-                .map(|decrypted_record| (commitment, ciphertext, decrypted_record))
-                .ok()
-                .filter(|(commitment, _, _)| {
-                    let serial_number =
-                        vm::compute_serial_number(credentials.private_key, *commitment);
-                    serial_number.is_ok() && !spent_records.contains(&serial_number.unwrap())
-                })
+    let wallet = sync_wallet(credentials, url).await?;
+    let records = wallet
+        .unspent_records()
+        .map(|record| {
+            (
+                record.commitment,
+                record.ciphertext.clone(),
+                record.record.clone(),
+            )
         })
         .collect();
     Ok(records)
@@ -502,6 +740,23 @@ async fn get_program_from_blockchain(
     Ok(program)
 }
 
+/// Fetch the programs imported by the given one from the blockchain, along with the ones they import.
+async fn get_imports(url: &str, program: &vm::Program) -> Result<Vec<vm::Program>> {
+    let mut imports: Vec<vm::Program> = vec![];
+    let mut pending: Vec<vm::ProgramID> = program.imports().keys().copied().collect();
+    while let Some(program_id) = pending.pop() {
+        if imports.iter().any(|import| *import.id() == program_id) {
+            continue;
+        }
+        let import = get_program_from_blockchain(url, program_id)
+            .await?
+            .ok_or_else(|| anyhow!("Imported program {program_id} is not deployed"))?;
+        pending.extend(import.imports().keys().copied());
+        imports.push(import);
+    }
+    Ok(imports)
+}
+
 /// Select one of the records to be used to pay the requested fee,
 /// that is not already being used as input to the execution.
 /// The biggest record is chosen as the default under the assumption
@@ -1,4 +1,5 @@
 use clap::Parser;
+use lib::error::AppError;
 use serde_json::json;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
@@ -6,6 +7,7 @@ use tracing_subscriber::EnvFilter;
 mod account;
 mod commands;
 mod tendermint;
+mod wallet;
 
 /// Default tendermint url
 const LOCAL_BLOCKCHAIN_URL: &str = "http://127.0.0.1:26657";
@@ -42,7 +44,14 @@ async fn main() {
 
     let (exit_code, output) = match cli.command.run(cli.url).await {
         Ok(output) => (0, output),
-        Err(err) => (1, json!({"error": err.to_string()})),
+        Err(err) => match err.downcast_ref::<AppError>() {
+            // errors returned by the blockchain include their code, so scripts don't need to parse the message
+            Some(app_error) => (
+                1,
+                json!({"error": format!("{err:#}"), "code": app_error.code()}),
+            ),
+            None => (1, json!({"error": err.to_string()})),
+        },
     };
 
     println!("{output:#}");
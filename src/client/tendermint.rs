@@ -1,30 +1,233 @@
-use anyhow::{bail, ensure, Result};
+use anyhow::{anyhow, ensure, Result};
+use lib::error::{AppError, CODESPACE};
+use lib::events;
+use lib::merkle::{self, StateProof};
+use lib::query::AbciQuery;
+use lib::transaction::MAX_TRANSACTION_SIZE;
 use log::debug;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tendermint::block::Height;
+use tendermint_rpc::endpoint::tx;
 use tendermint_rpc::query::Query;
 use tendermint_rpc::{Client, HttpClient, Order};
 
-pub async fn get_transaction(tx_id: &str, url: &str) -> Result<Vec<u8>> {
-    let client = HttpClient::new(url)?;
-    // todo: this index key might have to be a part of the shared lib so that both the CLI and the ABCI can be in sync
-    let query = Query::contains("app.tx_id", tx_id);
+/// How many times to poll for the block that includes the app hash of a queried height.
+const APP_HASH_RETRIES: usize = 10;
 
-    let response = client
-        .tx_search(query, false, 1, 1, Order::Ascending)
-        .await?;
+/// How many results of the same transaction to look at when searching it. The same transaction could be included
+/// more than once, e.g. if it failed and was broadcast again.
+const TRANSACTION_SEARCH_RESULTS: u8 = 10;
+
+/// How many transactions to fetch in each request when searching them, the maximum allowed by tendermint.
+const SEARCH_PAGE_SIZE: u8 = 100;
+
+/// How many transactions of the mempool to fetch when looking for a pending one, the maximum allowed by tendermint.
+const UNCONFIRMED_TXS_LIMIT: u8 = 100;
+
+/// The status of a transaction in the blockchain.
+pub enum TransactionStatus {
+    /// Accepted to the mempool of the node, waiting to be included in a block.
+    Pending,
+    /// Included in a block and successfully applied to the ledger.
+    Committed {
+        height: u64,
+        index: u32,
+        transaction: Vec<u8>,
+    },
+    /// Included in a block but rejected when delivered, without changing the ledger.
+    Failed {
+        height: u64,
+        index: u32,
+        error: AppError,
+    },
+    /// Not known by the node: it was never sent, it was rejected by the mempool, or it's not committed yet.
+    Unknown,
+}
+
+pub async fn get_transaction(tx_id: &str, url: &str) -> Result<Vec<u8>> {
+    let result = search_transaction(tx_id, url).await?;
 
     // early return with error if no transaction has been indexed for that tx id
-    ensure!(
-        response.total_count > 0,
-        "Transaction ID {} is invalid or has not yet been committed to the blockchain",
-        tx_id
+    let result = result.ok_or_else(|| {
+        anyhow!(
+            "Transaction ID {} is invalid or has not yet been committed to the blockchain",
+            tx_id
+        )
+    })?;
+
+    if let tendermint::abci::Code::Err(code) = result.tx_result.code {
+        return Err(response_error(
+            code,
+            &result.tx_result.codespace.to_string(),
+            &result.tx_result.log.to_string(),
+            "Transaction failed",
+        ));
+    }
+    Ok(result.tx.into())
+}
+
+/// Return whether the transaction is pending in the mempool, was committed to the blockchain, failed or is unknown.
+pub async fn get_transaction_status(tx_id: &str, url: &str) -> Result<TransactionStatus> {
+    if let Some(result) = search_transaction(tx_id, url).await? {
+        let height = result.height.value();
+        let index = result.index;
+        let status = match result.tx_result.code {
+            tendermint::abci::Code::Ok => TransactionStatus::Committed {
+                height,
+                index,
+                transaction: result.tx.into(),
+            },
+            tendermint::abci::Code::Err(code) => TransactionStatus::Failed {
+                height,
+                index,
+                error: decode_error(
+                    code,
+                    &result.tx_result.codespace.to_string(),
+                    &result.tx_result.log.to_string(),
+                ),
+            },
+        };
+        return Ok(status);
+    }
+
+    // the transaction id is the hash of its encoding, so it can be compared with the transactions in the mempool
+    // without decoding them
+    let mempool = unconfirmed_transactions(url).await?;
+    if mempool
+        .transactions
+        .iter()
+        .any(|transaction| hex::encode(Sha256::digest(transaction)) == tx_id.to_lowercase())
+    {
+        return Ok(TransactionStatus::Pending);
+    }
+    if mempool.transactions.len() >= mempool.total {
+        return Ok(TransactionStatus::Unknown);
+    }
+
+    // tendermint lists only the first transactions of a bigger mempool, so only for the rest the client falls back to
+    // the claims the application keeps for the transactions it accepted to it
+    let response = query(
+        AbciQuery::IsPending {
+            transaction_id: tx_id.to_string(),
+        }
+        .into(),
+        url,
+    )
+    .await?;
+    if bincode::deserialize::<bool>(&response)? {
+        Ok(TransactionStatus::Pending)
+    } else {
+        Ok(TransactionStatus::Unknown)
+    }
+}
+
+/// The transactions in the mempool of a node, as listed by its `unconfirmed_txs` endpoint.
+struct Mempool {
+    /// The first transactions of the mempool, in the order they will be proposed.
+    transactions: Vec<Vec<u8>>,
+    /// The amount of transactions in the mempool, which can be more than the listed ones.
+    total: usize,
+}
+
+/// Fetch the transactions in the mempool of the node. The tendermint rpc client doesn't support the
+/// `unconfirmed_txs` endpoint, so it's requested directly.
+async fn unconfirmed_transactions(url: &str) -> Result<Mempool> {
+    #[derive(Deserialize)]
+    struct RpcResponse {
+        result: Option<UnconfirmedTxs>,
+        error: Option<serde_json::Value>,
+    }
+
+    // tendermint encodes its integers as strings
+    #[derive(Deserialize)]
+    struct UnconfirmedTxs {
+        total: String,
+        txs: Option<Vec<String>>,
+    }
+
+    let url = format!(
+        "{}/unconfirmed_txs?limit={UNCONFIRMED_TXS_LIMIT}",
+        url.trim_end_matches('/')
     );
+    let response: RpcResponse = reqwest::get(url).await?.json().await?;
+    let result = response.result.ok_or_else(|| {
+        anyhow!(
+            "Error fetching the mempool transactions: {}",
+            response.error.unwrap_or_default()
+        )
+    })?;
+    let transactions = result
+        .txs
+        .unwrap_or_default()
+        .iter()
+        .map(base64::decode)
+        .collect::<Result<_, _>>()?;
+    Ok(Mempool {
+        transactions,
+        total: result.total.parse()?,
+    })
+}
+
+/// Return the committed transactions matching the given query on the events emitted when they were delivered,
+/// in the order they were included in the blockchain.
+pub async fn search_transactions(query: Query, url: &str) -> Result<Vec<tx::Response>> {
+    let client = HttpClient::new(url)?;
+    let mut results = vec![];
+    let mut page = 1;
+    loop {
+        let response = client
+            .tx_search(
+                query.clone(),
+                false,
+                page,
+                SEARCH_PAGE_SIZE,
+                Order::Ascending,
+            )
+            .await?;
+        let last_page = response.txs.is_empty();
+        results.extend(response.txs);
+        if last_page || results.len() >= response.total_count as usize {
+            return Ok(results);
+        }
+        page += 1;
+    }
+}
+
+/// Return the indexed result of the delivery of the transaction, preferring a successful one if it was included
+/// more than once, or none if it's not included in any block.
+async fn search_transaction(tx_id: &str, url: &str) -> Result<Option<tx::Response>> {
+    let client = HttpClient::new(url)?;
+    let query = Query::contains(events::TX_ID.query_key(), tx_id);
 
-    let tx_bytes: Vec<u8> = response.txs.into_iter().next().unwrap().tx.into();
+    let response = client
+        .tx_search(
+            query,
+            false,
+            1,
+            TRANSACTION_SEARCH_RESULTS,
+            Order::Ascending,
+        )
+        .await?;
 
-    Ok(tx_bytes)
+    let successful = response
+        .txs
+        .iter()
+        .position(|result| matches!(result.tx_result.code, tendermint::abci::Code::Ok));
+    let mut results = response.txs;
+    Ok(match successful {
+        Some(position) => Some(results.swap_remove(position)),
+        None => results.pop(),
+    })
 }
 
 pub async fn broadcast(transaction: Vec<u8>, url: &str) -> Result<()> {
+    ensure!(
+        transaction.len() <= MAX_TRANSACTION_SIZE,
+        "transaction of {} bytes exceeds the maximum of {MAX_TRANSACTION_SIZE}",
+        transaction.len()
+    );
     let client = HttpClient::new(url).unwrap();
 
     let tx: tendermint::abci::Transaction = transaction.into();
@@ -34,9 +237,13 @@ pub async fn broadcast(transaction: Vec<u8>, url: &str) -> Result<()> {
     debug!("Response from CheckTx: {:?}", response);
     match response.code {
         tendermint::abci::Code::Ok => Ok(()),
-        tendermint::abci::Code::Err(code) => {
-            bail!("Error executing transaction {}: {}", code, response.log)
-        }
+        // the broadcast response doesn't include the codespace, so the code is assumed to come from the application
+        tendermint::abci::Code::Err(code) => Err(response_error(
+            code,
+            CODESPACE,
+            &response.log.to_string(),
+            "Error executing transaction",
+        )),
     }
 }
 
@@ -48,8 +255,106 @@ pub async fn query(query: Vec<u8>, url: &str) -> Result<Vec<u8>> {
     debug!("Response from Query: {:?}", response);
     match response.code {
         tendermint::abci::Code::Ok => Ok(response.value),
-        tendermint::abci::Code::Err(code) => {
-            bail!("Error executing transaction {}: {}", code, response.log)
+        tendermint::abci::Code::Err(code) => Err(response_error(
+            code,
+            &response.codespace,
+            &response.log.to_string(),
+            "Error executing query",
+        )),
+    }
+}
+
+/// Run a query requesting merkle proofs of its results, and check that the state roots they are proven against
+/// match the app hash of the block header after the queried height. The returned proofs can then be used to verify
+/// the records and serial numbers included in the response value.
+pub async fn proven_query(query: Vec<u8>, url: &str) -> Result<(Vec<u8>, StateProof)> {
+    let client = HttpClient::new(url).unwrap();
+
+    let response = client.abci_query(None, query, None, true).await?;
+
+    debug!("Response from Query: {:?}", response);
+    if let tendermint::abci::Code::Err(code) = response.code {
+        return Err(response_error(
+            code,
+            &response.codespace,
+            &response.log.to_string(),
+            "Error executing query",
+        ));
+    }
+
+    let ops = response.proof.map(|proof| proof.ops).unwrap_or_default();
+    let proof = StateProof::from_ops(ops.iter().map(|op| {
+        (
+            op.field_type.as_str(),
+            op.key.as_slice(),
+            op.data.as_slice(),
+        )
+    }))?;
+
+    // the app hash resulting from committing a block is included in the header of the next one
+    let app_hash = get_app_hash(&client, response.height.increment()).await?;
+    proof.verify_app_hash(&app_hash)?;
+    Ok((response.value, proof))
+}
+
+/// Return the latest record commitment tree root, verified against the app hash of the block headers, if the chain
+/// requires transactions to reveal their input records, or none otherwise.
+/// Transactions reference it to show that their input records exist in the ledger.
+pub async fn get_state_root(url: &str) -> Result<Option<merkle::Hash>> {
+    let response = query(AbciQuery::RevealsInputRecords.into(), url).await?;
+    if !bincode::deserialize::<bool>(&response)? {
+        return Ok(None);
+    }
+    let (_, proof) = proven_query(AbciQuery::GetStateRoots.into(), url).await?;
+    let roots = proof
+        .roots
+        .ok_or_else(|| anyhow!("query response doesn't include the state roots"))?;
+    Ok(Some(roots.commitments))
+}
+
+/// Return the id of the chain the node belongs to.
+pub async fn get_chain_id(url: &str) -> Result<String> {
+    let client = HttpClient::new(url).unwrap();
+    let status = client.status().await?;
+    Ok(status.node_info.network.to_string())
+}
+
+/// Return the height of the latest block committed by the node.
+pub async fn get_height(url: &str) -> Result<u64> {
+    let client = HttpClient::new(url).unwrap();
+    let status = client.status().await?;
+    Ok(status.sync_info.latest_block_height.value())
+}
+
+/// Decode the code of a failed abci response into the application error that caused it, so callers can
+/// inspect it by downcasting the returned error. Codes from other codespaces are decoded as internal errors.
+fn response_error(code: u32, codespace: &str, log: &str, context: &str) -> anyhow::Error {
+    anyhow::Error::new(decode_error(code, codespace, log)).context(format!("{context} {code}"))
+}
+
+/// Decode the code of a failed abci response into the application error that caused it.
+/// Codes from other codespaces are decoded as internal errors.
+fn decode_error(code: u32, codespace: &str, log: &str) -> AppError {
+    if codespace.is_empty() || codespace == CODESPACE {
+        AppError::from_code(code, log.to_string())
+    } else {
+        AppError::Internal(format!("{codespace}: {log}"))
+    }
+}
+
+/// Return the app hash included in the header of the block at the given height,
+/// waiting for it to be produced if necessary.
+async fn get_app_hash(client: &HttpClient, height: Height) -> Result<Vec<u8>> {
+    let mut retries = 0;
+    loop {
+        match client.block(height).await {
+            Ok(response) => return Ok(response.block.header.app_hash.value()),
+            Err(e) if retries < APP_HASH_RETRIES => {
+                debug!("Block {} not available yet: {}", height, e);
+                retries += 1;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => return Err(e.into()),
         }
     }
 }
@@ -0,0 +1,168 @@
+use crate::{account, tendermint};
+use anyhow::{anyhow, ensure, Result};
+use lib::error::AppError;
+use lib::query::{AbciQuery, MAX_RECORDS_PAGE_SIZE};
+use lib::vm;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// File that stores the records owned by an account, along with their serial numbers and spent status,
+/// and the position to continue syncing from: a block height and, if the last synced page ended in the middle of
+/// that block, the commitment of the first record left out.
+/// Stores it at $ALEO_HOME/wallet.json, with ~/.aleo as the default ALEO_HOME.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Wallet {
+    pub chain_id: String,
+    pub address: String,
+    pub height: u64,
+    #[serde(default)]
+    pub from: Option<vm::Field>,
+    pub records: Vec<WalletRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WalletRecord {
+    pub commitment: vm::Field,
+    pub ciphertext: vm::EncryptedRecord,
+    pub record: vm::Record,
+    pub serial_number: vm::Field,
+    pub spent: bool,
+}
+
+impl Wallet {
+    /// Load the wallet of the given account and chain. If it's missing, or it belongs to a different account
+    /// or chain (e.g. the chain was restarted), start a new one from height zero.
+    pub fn load(chain_id: &str, address: &vm::Address) -> Self {
+        let address = address.to_string();
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|wallet_json| serde_json::from_str::<Self>(&wallet_json).ok())
+            .filter(|wallet| wallet.chain_id == chain_id && wallet.address == address)
+            .unwrap_or_else(|| Self {
+                chain_id: chain_id.to_string(),
+                address,
+                ..Default::default()
+            })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = Self::path();
+        let dir = file.parent().unwrap();
+        fs::create_dir_all(dir)?;
+        debug!("Saving wallet to {}", file.to_string_lossy());
+        let wallet_json = serde_json::to_string(&self)?;
+        fs::write(file, wallet_json)?;
+        Ok(())
+    }
+
+    /// Scan the records added to the ledger since the last synced height, keeping the ones owned by the account,
+    /// and update the spent status of the known records.
+    /// The node responses are verified with merkle proofs against the app hash of the block headers.
+    pub async fn sync(&mut self, credentials: &account::Credentials, url: &str) -> Result<()> {
+        loop {
+            let query = AbciQuery::GetRecordsSince {
+                height: self.height,
+                from: self.from,
+                limit: Some(MAX_RECORDS_PAGE_SIZE),
+            };
+            let height = self.height;
+            let (get_records_response, records_proof) =
+                tendermint::proven_query(query.into(), url)
+                    .await
+                    .map_err(|error| match AppError::find(&error) {
+                        // nodes pruning their records can't sync wallets older than their retained blocks
+                        AppError::PrunedRecords(message) => anyhow!(
+                            "can't sync the wallet from height {height}, the node {url} says: {message}. \
+                             Sync it from an archive node, one that doesn't run with --prune-records, \
+                             by passing its url with --url"
+                        ),
+                        _ => error,
+                    })?;
+            let (records, next_height, next_from): (
+                Vec<(vm::Field, vm::EncryptedRecord)>,
+                u64,
+                Option<vm::Field>,
+            ) = bincode::deserialize(&get_records_response)?;
+
+            // NOTE: the proofs show that the returned records are part of the ledger,
+            // but a node could still omit some of them in its response.
+            for (commitment, ciphertext) in &records {
+                records_proof.verify_record(commitment, ciphertext)?;
+            }
+
+            debug!(
+                "Scanned {} records since height {}",
+                records.len(),
+                self.height
+            );
+            let is_last_page = records.is_empty();
+            for (commitment, ciphertext) in records {
+                if !ciphertext.is_owner(&credentials.address, &credentials.view_key) {
+                    continue;
+                }
+                let record = ciphertext.decrypt(&credentials.view_key)?;
+                let serial_number = vm::compute_serial_number(credentials.private_key, commitment)?;
+                self.records.push(WalletRecord {
+                    commitment,
+                    ciphertext,
+                    record,
+                    serial_number,
+                    spent: false,
+                });
+            }
+            self.height = next_height;
+            self.from = next_from;
+            self.save()?;
+
+            if is_last_page {
+                break;
+            }
+        }
+
+        // only ask about the serial numbers of the owned records not known to be spent
+        let serial_numbers: Vec<vm::Field> = self
+            .unspent_records()
+            .map(|record| record.serial_number)
+            .collect();
+        if serial_numbers.is_empty() {
+            return Ok(());
+        }
+
+        let query = AbciQuery::IsSpent {
+            serial_numbers: serial_numbers.clone(),
+        };
+        let (is_spent_response, spent_proof) = tendermint::proven_query(query.into(), url).await?;
+        let are_spent: Vec<bool> = bincode::deserialize(&is_spent_response)?;
+        ensure!(
+            are_spent.len() == serial_numbers.len(),
+            "unexpected spent status response length"
+        );
+
+        let mut spent_serial_numbers = HashSet::new();
+        for (serial_number, is_spent) in serial_numbers.into_iter().zip(are_spent) {
+            if is_spent {
+                spent_proof.verify_serial_number(&serial_number)?;
+                spent_serial_numbers.insert(serial_number);
+            }
+        }
+
+        for record in self.records.iter_mut() {
+            if spent_serial_numbers.contains(&record.serial_number) {
+                record.spent = true;
+            }
+        }
+        self.save()
+    }
+
+    /// Return the owned records that are not known to be spent.
+    pub fn unspent_records(&self) -> impl Iterator<Item = &WalletRecord> {
+        self.records.iter().filter(|record| !record.spent)
+    }
+
+    fn path() -> PathBuf {
+        lib::aleo_home().join("wallet.json")
+    }
+}
@@ -0,0 +1,2077 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::blockchain::block_verifier::BlockVerifier;
+use crate::blockchain::mempool::PendingClaims;
+use crate::blockchain::program_store::ProgramStore;
+use crate::blockchain::record_store::RecordStore;
+use crate::blockchain::snapshots::Snapshots;
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::blockchain::validator_set::ValidatorSet;
+use crate::blockchain::verification_cache::VerificationCache;
+use crate::error::{AppError, CODESPACE};
+use crate::events::{self, EventKey};
+use crate::fees::FeeSchedule;
+use crate::merkle::{self, Hash, StateRoots};
+use crate::query::{AbciQuery, MAX_RECORDS_PAGE_SIZE};
+use crate::validator::GenesisState;
+use crate::{transaction::Transaction, vm};
+use anyhow::{anyhow, bail, ensure, Result};
+use itertools::Itertools;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use tendermint_abci::Application;
+use tendermint_proto::abci;
+use tendermint_proto::crypto::{ProofOp, ProofOps};
+
+use tracing::{debug, error, info};
+
+/// An Tendermint ABCI application that works with a SnarkVM backend.
+/// This struct implements the ABCI application hooks, forwarding commands through
+/// a channel for the parts that require knowledge of the application state and the SnarkVM details.
+/// For reference see https://docs.tendermint.com/v0.34/introduction/what-is-tendermint.html#abci-overview
+#[derive(Debug, Clone)]
+pub struct SnarkVMApp {
+    records: RecordStore,
+    programs: ProgramStore,
+
+    // NOTE: Wrapping in mutex here because we need mut access to ValidatorSet and the alternative to setup
+    // a channel was overkilll for this particular case. Also, at the moment we only ever access these field
+    // from a single tendermint abci connection (the consensus connection), but using Rc instead of Arc would
+    // introduce subtle bugs should that ever change.
+    validators: Arc<Mutex<ValidatorSet>>,
+
+    // The minimum fee parameters, set on genesis.
+    fees: Arc<RwLock<FeeSchedule>>,
+
+    // The id of the chain, set on genesis, that transactions need to include to be accepted.
+    chain_id: Arc<RwLock<String>>,
+
+    // Whether transactions need to reveal their input records, set on genesis.
+    reveal_input_records: Arc<AtomicBool>,
+
+    // The height of the block being delivered, or of the last committed one between blocks, set on begin block.
+    // Used to reject expired transactions.
+    height: Arc<AtomicI64>,
+
+    // The transitions with already verified proofs, shared by the mempool and consensus connections.
+    verification_cache: Arc<VerificationCache>,
+
+    // Verifies the proofs of the transactions of each block in the background when it begins, if enabled in the settings.
+    block_verifier: Option<BlockVerifier>,
+
+    // The serial numbers spent by the transactions in the mempool, cleared by the consensus connection on commit.
+    pending_claims: Arc<Mutex<PendingClaims>>,
+
+    // The states opened to answer queries at past heights, most recent first, up to PAST_STATES_CACHE_SIZE.
+    past_states: Arc<Mutex<VecDeque<Arc<PastState>>>>,
+
+    // Queries are served from a different tendermint connection than the one that commits blocks. This lock
+    // prevents the committed state from changing while a query response and its proofs are being built.
+    commit_lock: Arc<RwLock<()>>,
+
+    // The database shared by all the stores, where the changes of each block are written atomically on commit.
+    storage: Arc<Storage>,
+    path: PathBuf,
+
+    // Snapshots of the state served to, or restored from, other nodes through tendermint state sync.
+    snapshots: Arc<Snapshots>,
+
+    settings: Settings,
+}
+
+/// Settings of the application that only affect the local node, not the consensus state.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    /// Take a snapshot of the state every this many blocks, or none if zero.
+    pub snapshot_interval: u64,
+    /// Amount of recent snapshots to keep.
+    pub snapshot_keep: usize,
+    /// Amount of recent blocks to keep, or all of them if zero. Tendermint prunes older blocks from its
+    /// block store, and the application drops the data needed to roll them back.
+    pub retain_blocks: u64,
+    /// Whether to also drop the ciphertexts of the records added before the retained blocks.
+    pub prune_records: bool,
+    /// Amount of transitions with verified proofs to remember, or none if zero.
+    pub verification_cache_size: usize,
+    /// URL of the tendermint RPC of the node, to fetch the transactions of each block when it begins and verify
+    /// their proofs in parallel in the background, before they are delivered. If none, they are verified as each
+    /// one is delivered.
+    pub tendermint_url: Option<String>,
+}
+
+/// Name of the database where the application state is stored, relative to the application home directory.
+const STORAGE_PATH: &str = "abci.db";
+
+/// Name of the directory where the state snapshots are stored, relative to the application home directory.
+const SNAPSHOTS_PATH: &str = "snapshots";
+
+/// Key of the meta column where the height and state roots of the last committed block are stored.
+const LAST_BLOCK_KEY: &[u8] = b"last_block";
+
+/// Key of the meta column where the fee schedule set on genesis is stored.
+const FEE_SCHEDULE_KEY: &[u8] = b"fee_schedule";
+
+/// Key of the meta column where the chain id set on genesis is stored.
+const CHAIN_ID_KEY: &[u8] = b"chain_id";
+
+/// Key of the meta column where the genesis requirement of revealing input records is stored.
+const REVEAL_INPUT_RECORDS_KEY: &[u8] = b"reveal_input_records";
+
+/// Counter used to name the temporary copies of the storage used to answer queries at past heights.
+static QUERY_CHECKPOINTS: AtomicU64 = AtomicU64::new(0);
+
+/// Amount of past states kept open to answer queries at past heights.
+const PAST_STATES_CACHE_SIZE: usize = 4;
+
+impl Application for SnarkVMApp {
+    /// This hook is called once upon genesis. It's used to load a default set of records which
+    /// make the initial distribution of credits in the system.
+    fn init_chain(&self, request: abci::RequestInitChain) -> abci::ResponseInitChain {
+        info!("Loading genesis");
+
+        // the app_state_bytes come from the app_state field of the tendermint genesis.json generated by genesis.rs
+        let state: GenesisState =
+            serde_json::from_slice(&request.app_state_bytes).expect("invalid genesis state");
+
+        for (commitment, record) in state.records {
+            debug!("Storing genesis record {}", commitment);
+            self.records
+                .add(commitment, record)
+                .expect("failure adding genesis records");
+        }
+
+        self.validators.lock().unwrap().replace(state.validators);
+
+        // the chain id is the one of the tendermint genesis, which clients get from the node status
+        assert!(
+            state.chain_id.is_empty() || state.chain_id == request.chain_id,
+            "genesis state chain id {} doesn't match the tendermint chain id {}",
+            state.chain_id,
+            request.chain_id
+        );
+
+        // unlike the rest of the genesis state, the fee schedule, chain id and input records requirement are written
+        // right away: if the node crashes before committing the first block, init chain is called again and overwrites them
+        let mut batch = Batch::default();
+        batch.put(
+            Column::Meta,
+            FEE_SCHEDULE_KEY,
+            bincode::serialize(&state.fees).unwrap(),
+        );
+        batch.put(Column::Meta, CHAIN_ID_KEY, &request.chain_id);
+        batch.put(
+            Column::Meta,
+            REVEAL_INPUT_RECORDS_KEY,
+            [state.reveal_input_records as u8],
+        );
+        self.storage
+            .write(batch)
+            .expect("failure writing the genesis parameters");
+        *self.fees.write().unwrap() = state.fees;
+        *self.chain_id.write().unwrap() = request.chain_id;
+        self.reveal_input_records
+            .store(state.reveal_input_records, Ordering::SeqCst);
+        Default::default()
+    }
+
+    /// This hook provides information about the ABCI application.
+    fn info(&self, request: abci::RequestInfo) -> abci::ResponseInfo {
+        debug!(
+            "Got info request. Tendermint version: {}; Block version: {}; P2P version: {}",
+            request.version, request.block_version, request.p2p_version
+        );
+
+        let (last_block_height, last_roots) = self.last_block();
+        let last_block_app_hash = last_roots.map(|roots| roots.app_hash()).unwrap_or_default();
+        abci::ResponseInfo {
+            data: "snarkvm-app".to_string(),
+            version: "0.1.0".to_string(),
+            app_version: 1,
+            last_block_height,
+            last_block_app_hash,
+        }
+    }
+
+    /// This hook is to query the application for data at the current or past height.
+    /// When a proof is requested, the response includes merkle proofs for the returned records and serial numbers,
+    /// along with the state roots they are proven against. Clients can verify these roots with the app hash
+    /// of the block header at the next height.
+    /// Queries at a past height are answered from a copy of the storage rolled back to it, which fails if the
+    /// height is outside of the retained blocks.
+    fn query(&self, request: abci::RequestQuery) -> abci::ResponseQuery {
+        let guard = self.commit_lock.read().unwrap();
+        let (last_height, _) = self.last_block();
+        if request.height == 0 || request.height == last_height {
+            return self.run_query(request);
+        }
+
+        // the verification cache and the pending claims are kept in memory and only reflect the current state of the
+        // node, so a past state, which starts with them empty, can't answer them
+        if matches!(
+            bincode::deserialize(&request.data),
+            Ok(AbciQuery::GetVerificationCacheStats | AbciQuery::IsPending { .. })
+        ) {
+            return query_error(
+                &AppError::UnavailableHeight(format!(
+                    "this query is only answered at the last height {last_height}"
+                ))
+                .into(),
+            );
+        }
+
+        // the last past states opened are kept to answer the following queries at the same heights, so each historical
+        // query doesn't copy and roll back the storage again
+        let cached = self
+            .past_states
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|state| state.height == request.height)
+            .cloned();
+        let state = match cached {
+            Some(state) => {
+                drop(guard);
+                Ok(state)
+            }
+            None => {
+                // only copying the storage needs the commit lock, and the cache is only locked to insert the opened
+                // state, so opening the copy doesn't hold back the blocks being committed or the queries to other heights
+                let checkpoint = self.checkpoint(request.height);
+                drop(guard);
+                checkpoint
+                    .and_then(|path| PastState::open(path, request.height))
+                    .map(|state| {
+                        let mut past_states = self.past_states.lock().unwrap();
+                        // a concurrent query may have opened the same height meanwhile, in which case this copy is
+                        // dropped and removed
+                        if let Some(opened) = past_states
+                            .iter()
+                            .find(|opened| opened.height == request.height)
+                        {
+                            return opened.clone();
+                        }
+                        let state = Arc::new(state);
+                        past_states.push_front(state.clone());
+                        past_states.truncate(PAST_STATES_CACHE_SIZE);
+                        state
+                    })
+            }
+        };
+        state
+            .map(|state| state.run_query(request))
+            .unwrap_or_else(|e| query_error(&e))
+    }
+
+    /// This ABCI hook validates an incoming transaction before inserting it in the
+    /// mempool and relaying it to other nodes.
+    fn check_tx(&self, request: abci::RequestCheckTx) -> abci::ResponseCheckTx {
+        info!("Check Tx");
+
+        let tx = match Transaction::from_bytes(&request.tx) {
+            Ok(tx) => tx,
+            Err(err) => return check_tx_error(&err),
+        };
+
+        // after every block tendermint rechecks the transactions left in the mempool. Their proofs were already verified
+        // when they were first checked, and that doesn't depend on the ledger state, so only the checks that could have
+        // been invalidated by the committed block are run again, to promptly evict the transactions that conflict with it.
+        let result = if request.r#type == abci::CheckTxType::Recheck as i32 {
+            // the transactions that fail the recheck are evicted from the mempool
+            let result = self.recheck_transaction(&tx);
+            if result.is_err() {
+                self.pending_claims.lock().unwrap().remove_replaced(tx.id());
+            }
+            result
+        } else {
+            self.check_not_legacy(&tx)
+                .and_then(|_| self.check_not_expired(&tx, self.next_height()))
+                .and_then(|_| self.check_no_duplicate_records(&tx))
+                .and_then(|_| self.check_inputs_are_unspent(&tx))
+                .and_then(|_| self.check_pending_claims(&tx))
+                .and_then(|_| self.validate_transaction(&tx))
+        }
+        .map(|_| self.claim_inputs(&tx));
+
+        // by making the priority equal to the fees we give more priority to higher-paying transactions
+        // NOTE: we haven't thoroughly tested tendermint prioritized mempool, see for background
+        // https://github.com/tendermint/tendermint/discussions/9772
+        let priority = tx.fees();
+
+        if let Err(err) = result {
+            check_tx_error(&err)
+        } else {
+            abci::ResponseCheckTx {
+                priority,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// This hook is called before the app starts processing transactions on a block.
+    /// Used to store current proposer and the previous block's voters to assign fees and coinbase
+    /// credits when the block is committed.
+    fn begin_block(&self, request: abci::RequestBeginBlock) -> abci::ResponseBeginBlock {
+        // a call to begin block without header doesn't seem to make sense, verify it can happen
+        // supporting this case is cumbersome, assuming it won't happen until proven wrong
+        let header = request
+            .header
+            .expect("received block without header, aborting");
+
+        // tendermint replays the blocks after the app's last height during the handshake, so blocks should always
+        // be received in order. Otherwise the app state can't be reconciled with the chain and it's better to crash.
+        let (last_height, _) = self.last_block();
+        assert_eq!(
+            last_height + 1,
+            header.height,
+            "received block at height {} but the last committed height is {last_height}",
+            header.height
+        );
+
+        // store current block proposer and previous block voters in the validator set
+        // NOTE: because of how tendermint makes information available to this hook,
+        // the block rewards go to this block's porposer and the **previous** block voters.
+        // This could be revisited if it's a problem.
+        let votes = request
+            .last_commit_info
+            .map(|last_commit| last_commit.votes)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|vote_info| {
+                if !vote_info.signed_last_block {
+                    // don't count validators that didn't participate in previous round
+                    return None;
+                }
+
+                if let Some(validator) = vote_info.validator.clone() {
+                    if validator.power < 0 {
+                        error!("received negative validator vote");
+                        None
+                    } else {
+                        Some((validator.address, validator.power as u64))
+                    }
+                } else {
+                    // If there's no associated validator data, we can't use this vote
+                    None
+                }
+            })
+            .collect();
+
+        self.validators.lock().unwrap().begin_block(
+            &header.proposer_address,
+            votes,
+            header.height as u64,
+        );
+        self.height.store(header.height, Ordering::SeqCst);
+
+        if let Some(block_verifier) = &self.block_verifier {
+            block_verifier.verify(header.height, &request.hash);
+        }
+
+        Default::default()
+    }
+
+    /// This ABCI hook validates a transaction and applies it to the application state,
+    /// for example storing the program verifying keys upon a valid deployment.
+    /// Here is also where transactions are indexed for querying the blockchain.
+    fn deliver_tx(&self, request: abci::RequestDeliverTx) -> abci::ResponseDeliverTx {
+        info!("Deliver Tx");
+
+        let tx = match Transaction::from_bytes(&request.tx) {
+            Ok(tx) => tx,
+            Err(err) => return deliver_tx_error(&err),
+        };
+        // the transaction leaves the mempool once it's included in a block, even if it was replaced by another one
+        self.pending_claims.lock().unwrap().remove_replaced(tx.id());
+
+        // we need to repeat the same validations as deliver_tx and only, because the protocol can't
+        // guarantee that a bynzantine validator won't propose a block with invalid transactions.
+        // if validation they pass  apply (but not commit) the application state changes.
+        // Note that we check for duplicate records within the transaction before attempting to spend them
+        // so we don't end up with a half-applied transaction in the record store.
+        let result = self
+            .check_not_legacy(&tx)
+            .and_then(|_| self.check_not_expired(&tx, self.height.load(Ordering::SeqCst)))
+            .and_then(|_| self.check_no_duplicate_records(&tx))
+            .and_then(|_| self.check_inputs_are_unspent(&tx))
+            .and_then(|_| self.validate_transaction(&tx))
+            // the events are built before applying the changes, so they can't fail after they are half-applied
+            .and_then(|_| transaction_events(&tx))
+            .and_then(|tx_events| {
+                self.update_validators(&tx)?;
+                self.spend_input_records(&tx)?;
+                self.add_output_records(&tx)?;
+                self.store_program(&tx)?;
+                Ok(tx_events)
+            });
+
+        // index the transaction to be queried by app.tx_id, even if it failed, so clients can tell it apart from one
+        // that wasn't committed yet. The result of the transaction tells if it failed and why.
+        let (tx_events, response) = match result {
+            Ok(tx_events) => (tx_events, Default::default()),
+            Err(e) => (vec![event(events::TX_ID, tx.id())], deliver_tx_error(&e)),
+        };
+        abci::ResponseDeliverTx {
+            events: tx_events,
+            ..response
+        }
+    }
+
+    /// Applies validator set updates based on staking transactions included in the block.
+    /// For details about validator set update semantics see:
+    /// https://github.com/tendermint/tendermint/blob/v0.34.x/spec/abci/apps.md#endblock
+    fn end_block(&self, _request: abci::RequestEndBlock) -> abci::ResponseEndBlock {
+        let validator_set = self.validators.lock().unwrap();
+        let validator_updates = validator_set
+            .pending_updates()
+            .iter()
+            .map(|validator| abci::ValidatorUpdate {
+                pub_key: Some(validator.pub_key.into()),
+                power: validator.voting_power as i64,
+            })
+            .collect();
+
+        abci::ResponseEndBlock {
+            validator_updates,
+            ..Default::default()
+        }
+    }
+
+    /// This hook commits is called when the block is comitted (after deliver_tx has been called for each transaction).
+    /// Changes to application should take effect here. Tendermint guarantees that no transaction is processed while this
+    /// hook is running.
+    /// The result includes a hash of the application state which will be included in the block header.
+    /// This hash should be deterministic, different app state hashes will produce blockchain forks.
+    /// New credits records are created to assign validator rewards.
+    fn commit(&self) -> abci::ResponseCommit {
+        // the app hash captures the state of the stores (records, spent serial numbers, programs and validators)
+        // so nodes that diverged (e.g. because of a bug or a partially applied block) produce a different hash
+        // and tendermint halts instead of silently forking. See below for more discussion on the use of app hash:
+        // https://github.com/tendermint/tendermint/issues/1179
+        // https://github.com/tendermint/tendermint/blob/v0.34.x/spec/abci/apps.md#query-proofs
+        // The record store returns the roots of its merkle trees, so query results can be proven against the app hash.
+        // The rest of the state is hashed as a whole, so a node restoring a snapshot can check it, and the previous
+        // app hash is included to chain every block since genesis.
+        // We rather crash than continue with a state we can't account for.
+        let _guard = self.commit_lock.write().unwrap();
+        let (last_height, last_roots) = self.last_block();
+        let height = last_height + 1;
+
+        // the rewards are added before committing the record store, so they are persisted along with the rest of the block
+        let validators = self.validators.lock().unwrap();
+        for (commitment, record) in validators.block_rewards() {
+            self.records
+                .add(commitment, record)
+                .expect("failure adding reward records");
+        }
+
+        // apply pending changes in the record store: mark used records as spent, add inputs as unspent
+        let ((commitments_root, serial_numbers_root), mut batch) = self
+            .records
+            .commit(height as u64)
+            .expect("failure while committing the record store");
+        let retain_height = self.retain_height(height);
+        if self.settings.prune_records && retain_height > 0 {
+            batch.append(
+                self.records
+                    .prune(retain_height as u64)
+                    .expect("failure while pruning the record store"),
+            );
+        }
+        let (programs_hash, programs_batch) = self
+            .programs
+            .commit()
+            .expect("failure while committing the program store");
+        batch.append(programs_batch);
+        batch.append(
+            validators
+                .commit()
+                .expect("failure while committing the validator set"),
+        );
+
+        let roots = StateRoots {
+            commitments: commitments_root,
+            serial_numbers: serial_numbers_root,
+            rest: rest_hash(
+                &programs_hash,
+                &validators,
+                &self.fees.read().unwrap(),
+                &self.chain_id.read().unwrap(),
+                self.reveal_input_records.load(Ordering::SeqCst),
+            ),
+            previous: last_roots
+                .map(|roots| roots.app_hash().try_into().unwrap())
+                .unwrap_or_default(),
+        };
+        let app_hash = roots.app_hash();
+        batch.put(
+            Column::Meta,
+            LAST_BLOCK_KEY,
+            bincode::serialize(&(height, Some(roots))).unwrap(),
+        );
+
+        // all the block changes are written at once: if the node crashes before this point, none of them are persisted
+        // and the app restarts from the previous height, so tendermint replays the block during the handshake.
+        self.storage
+            .commit(height as u64, batch)
+            .expect("failure writing the block to the storage");
+        self.snapshots
+            .maybe_take(&self.storage, height as u64, roots);
+
+        // blocks before the retain height won't be available to be replayed, so there's no point in rolling back to them
+        if retain_height > 0 {
+            self.storage
+                .prune_undo(retain_height as u64)
+                .unwrap_or_else(|e| error!("failure pruning undo data: {e}"));
+        }
+
+        // the transactions left in the mempool claim their inputs again as they are rechecked against the new state
+        self.pending_claims.lock().unwrap().clear();
+
+        info!(
+            "Committing height {} app hash {}",
+            height,
+            hex::encode(&app_hash)
+        );
+        let cache_stats = self.verification_cache.stats();
+        debug!(
+            "Verification cache: {} hits, {} misses, {}/{} transitions",
+            cache_stats.hits, cache_stats.misses, cache_stats.size, cache_stats.capacity
+        );
+        abci::ResponseCommit {
+            data: app_hash,
+            retain_height,
+        }
+    }
+
+    /// This hook is called by tendermint to find the snapshots this node can serve to peers doing state sync.
+    fn list_snapshots(&self) -> abci::ResponseListSnapshots {
+        let snapshots = self.snapshots.list().unwrap_or_else(|e| {
+            error!("could not list snapshots: {e}");
+            vec![]
+        });
+        abci::ResponseListSnapshots { snapshots }
+    }
+
+    /// This hook is called on a new node doing state sync, when a peer offers a snapshot to restore.
+    /// The snapshot is only accepted if its state roots match the app hash, which tendermint verifies with the light client.
+    fn offer_snapshot(&self, request: abci::RequestOfferSnapshot) -> abci::ResponseOfferSnapshot {
+        let (height, _) = self.last_block();
+        let result = if height != 0 {
+            error!("can't restore a snapshot over the state at height {height}");
+            abci::response_offer_snapshot::Result::Abort
+        } else if let Some(snapshot) = request.snapshot {
+            // discard the chunks of a previous snapshot that was only partially restored
+            let result = self
+                .snapshots
+                .offer(&snapshot, &request.app_hash)
+                .and_then(|_| self.storage.clear());
+            match result {
+                Ok(()) => {
+                    info!("Restoring snapshot at height {}", snapshot.height);
+                    abci::response_offer_snapshot::Result::Accept
+                }
+                Err(e) => {
+                    error!("rejected snapshot at height {}: {e}", snapshot.height);
+                    abci::response_offer_snapshot::Result::Reject
+                }
+            }
+        } else {
+            abci::response_offer_snapshot::Result::Reject
+        };
+
+        abci::ResponseOfferSnapshot {
+            result: result as i32,
+        }
+    }
+
+    /// This hook is called when a peer doing state sync requests a chunk of one of the snapshots of this node.
+    fn load_snapshot_chunk(
+        &self,
+        request: abci::RequestLoadSnapshotChunk,
+    ) -> abci::ResponseLoadSnapshotChunk {
+        let chunk = self
+            .snapshots
+            .load_chunk(request.height, request.format, request.chunk)
+            .unwrap_or_else(|e| {
+                error!("could not load snapshot chunk {}: {e}", request.chunk);
+                vec![]
+            });
+        abci::ResponseLoadSnapshotChunk { chunk }
+    }
+
+    /// This hook is called with each chunk of the snapshot being restored, in order.
+    /// Chunks that don't match the hashes of the snapshot metadata are fetched again from a different peer.
+    /// Once the last chunk is written, the stores are reloaded and checked against the state roots of the snapshot.
+    fn apply_snapshot_chunk(
+        &self,
+        request: abci::RequestApplySnapshotChunk,
+    ) -> abci::ResponseApplySnapshotChunk {
+        let _guard = self.commit_lock.write().unwrap();
+        if let Err(e) = self.snapshots.check_chunk(request.index, &request.chunk) {
+            error!("could not apply snapshot chunk {}: {e}", request.index);
+            return abci::ResponseApplySnapshotChunk {
+                result: abci::response_apply_snapshot_chunk::Result::Retry as i32,
+                refetch_chunks: vec![request.index],
+                reject_senders: vec![request.sender],
+            };
+        }
+
+        // the chunk matches the snapshot metadata, so if it can't be applied the snapshot itself is invalid
+        let result = match self
+            .snapshots
+            .apply_chunk(&self.storage, request.index, &request.chunk)
+            .and_then(|roots| match roots {
+                Some(roots) => self.load_restored_state(roots).map(|_| true),
+                None => Ok(false),
+            }) {
+            Ok(false) => abci::response_apply_snapshot_chunk::Result::Accept,
+            Ok(true) => {
+                info!("Restored state at height {}", self.last_block().0);
+                abci::response_apply_snapshot_chunk::Result::Accept
+            }
+            Err(e) => {
+                // leave the storage empty so a different snapshot can be restored
+                error!("could not restore snapshot: {e}");
+                self.snapshots.abort_restore();
+                self.storage
+                    .clear()
+                    .and_then(|_| self.records.restore(0))
+                    .and_then(|_| self.programs.restore())
+                    .expect("could not discard the restored snapshot");
+                *self.validators.lock().unwrap() = ValidatorSet::default();
+                abci::response_apply_snapshot_chunk::Result::RejectSnapshot
+            }
+        };
+
+        abci::ResponseApplySnapshotChunk {
+            result: result as i32,
+            ..Default::default()
+        }
+    }
+}
+
+impl SnarkVMApp {
+    /// Create the application with its state stored in the given home directory.
+    pub fn new(home: &Path, settings: Settings) -> Self {
+        let snapshots = Snapshots::new(
+            home.join(SNAPSHOTS_PATH),
+            settings.snapshot_interval,
+            settings.snapshot_keep,
+        );
+        // we rather crash than start with badly initialized stores
+        Self::open(&home.join(STORAGE_PATH), snapshots, settings)
+            .expect("could not open the application state")
+    }
+
+    /// Restore the application state to the one committed at the given height, so a node can recover from a bad block.
+    /// On the next start, tendermint replays the blocks after that height from its block store.
+    pub fn rollback(home: &Path, height: i64) -> Result<()> {
+        let storage = Storage::open(home.join(STORAGE_PATH))?;
+        let rolled_back = rollback_storage(&storage, height)?;
+        info!("Rolled back {} blocks to height {}", rolled_back, height);
+        Ok(())
+    }
+
+    /// Create the application with its stores backed by the database at the given path.
+    fn open(path: &Path, snapshots: Snapshots, settings: Settings) -> Result<Self> {
+        let storage = Arc::new(Storage::open(path)?);
+        Self::from_storage(storage, path, snapshots, settings)
+    }
+
+    /// Create the application with its stores backed by an already open database, located at the given path.
+    fn from_storage(
+        storage: Arc<Storage>,
+        path: &Path,
+        snapshots: Snapshots,
+        settings: Settings,
+    ) -> Result<Self> {
+        let validators = ValidatorSet::load(&storage)?;
+        let fees = read_fee_schedule(&storage)?;
+        let chain_id = read_chain_id(&storage)?;
+        let reveal_input_records = read_reveal_input_records(&storage)?;
+        let (height, _) = read_last_block(&storage);
+        let programs = ProgramStore::new(storage.clone())?;
+        let verification_cache = Arc::new(VerificationCache::new(settings.verification_cache_size));
+        let block_verifier = settings
+            .tendermint_url
+            .as_deref()
+            .map(|url| BlockVerifier::new(url, programs.clone(), verification_cache.clone()))
+            .transpose()?;
+        let app = Self {
+            programs,
+            records: RecordStore::new(storage.clone())?,
+            validators: Arc::new(Mutex::new(validators)),
+            fees: Arc::new(RwLock::new(fees)),
+            chain_id: Arc::new(RwLock::new(chain_id)),
+            reveal_input_records: Arc::new(AtomicBool::new(reveal_input_records)),
+            height: Arc::new(AtomicI64::new(height)),
+            verification_cache,
+            block_verifier,
+            pending_claims: Arc::new(Mutex::new(PendingClaims::default())),
+            past_states: Arc::new(Mutex::new(VecDeque::new())),
+            commit_lock: Arc::new(RwLock::new(())),
+            storage,
+            path: path.to_path_buf(),
+            snapshots: Arc::new(snapshots),
+            settings,
+        };
+
+        // blocks are written atomically, so the stores should always match the last committed roots.
+        // If they don't, the storage was tampered with or corrupted, and the node can't continue from it.
+        let (height, roots) = app.last_block();
+        if let Some(roots) = roots {
+            if (roots.commitments, roots.serial_numbers) != app.records.roots()? {
+                app.shutdown()?;
+                bail!("record store doesn't match the state committed at height {height}");
+            }
+        }
+        info!("Loaded state at height {}", height);
+        Ok(app)
+    }
+
+    /// Stop the store threads and wait for them to release the storage.
+    fn shutdown(self) -> Result<()> {
+        self.programs.shutdown()?;
+        self.records.shutdown()
+    }
+
+    /// Reload the stores after a snapshot was written to the storage, and check that they match its trusted state roots.
+    /// The record store recalculates its merkle trees from the stored records and serial numbers, and the rest of the
+    /// state (programs, validators, fee schedule, chain id and input records requirement) is hashed again, so nothing
+    /// in the snapshot is trusted without being verified against the roots.
+    fn load_restored_state(&self, roots: StateRoots) -> Result<()> {
+        let (height, last_roots) = self.last_block();
+        ensure!(
+            last_roots == Some(roots),
+            "last block at height {height} doesn't match the snapshot"
+        );
+        let store_roots = self.records.restore(height as u64)?;
+        ensure!(
+            (roots.commitments, roots.serial_numbers) == store_roots,
+            "record store doesn't match the snapshot"
+        );
+        let programs_hash = self.programs.restore()?;
+        let validators = ValidatorSet::load(&self.storage)?;
+        let fees = read_fee_schedule(&self.storage)?;
+        let chain_id = read_chain_id(&self.storage)?;
+        let reveal_input_records = read_reveal_input_records(&self.storage)?;
+        ensure!(
+            roots.rest
+                == rest_hash(
+                    &programs_hash,
+                    &validators,
+                    &fees,
+                    &chain_id,
+                    reveal_input_records
+                ),
+            "programs, validators or metadata don't match the snapshot"
+        );
+        *self.validators.lock().unwrap() = validators;
+        *self.fees.write().unwrap() = fees;
+        *self.chain_id.write().unwrap() = chain_id;
+        self.reveal_input_records
+            .store(reveal_input_records, Ordering::SeqCst);
+        self.height.store(height, Ordering::SeqCst);
+
+        // the past states opened before were copied from the replaced storage
+        self.past_states.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Answer the query with the last committed state.
+    fn run_query(&self, request: abci::RequestQuery) -> abci::ResponseQuery {
+        let (height, roots) = self.last_block();
+
+        // each query returns its value along with the commitments and serial numbers that can be proven
+        let query_result = match bincode::deserialize(&request.data) {
+            Ok(AbciQuery::GetRecords { from, limit }) => {
+                debug!("Fetching records from {:?}", from);
+                self.records
+                    .scan(from, Some(page_size(limit)))
+                    .map(|result| {
+                        let commitments: Vec<vm::Field> =
+                            result.0.iter().map(|(commitment, _)| *commitment).collect();
+                        (bincode::serialize(&result).unwrap(), commitments, vec![])
+                    })
+            }
+            Ok(AbciQuery::GetRecordsSince {
+                height: since,
+                from,
+                limit,
+            }) => {
+                debug!("Fetching records since height {} from {:?}", since, from);
+                self.records
+                    .scan_since(since, from, Some(page_size(limit)))
+                    .map(|(records, next)| {
+                        // when there are no more records, the caller can continue from the start of the next block
+                        let (next_height, next_from) = match next {
+                            Some((next_height, next_from)) => (next_height, Some(next_from)),
+                            None => (height as u64 + 1, None),
+                        };
+                        let commitments: Vec<vm::Field> =
+                            records.iter().map(|(commitment, _)| *commitment).collect();
+                        let value = bincode::serialize(&(records, next_height, next_from)).unwrap();
+                        (value, commitments, vec![])
+                    })
+            }
+            Ok(AbciQuery::GetRecord { commitment }) => {
+                debug!("Fetching record {}", commitment);
+                self.records.get(&commitment).map(|result| {
+                    let commitments = if result.is_some() {
+                        vec![commitment]
+                    } else {
+                        vec![]
+                    };
+                    (bincode::serialize(&result).unwrap(), commitments, vec![])
+                })
+            }
+            Ok(AbciQuery::GetSpentSerialNumbers) => {
+                debug!("Fetching spent records's serial numbers");
+
+                self.records.scan_spent().map(|result| {
+                    let serial_numbers: Vec<vm::Field> = result.iter().copied().collect();
+                    (bincode::serialize(&result).unwrap(), vec![], serial_numbers)
+                })
+            }
+            Ok(AbciQuery::IsSpent { serial_numbers }) => {
+                debug!(
+                    "Checking spent status of {} serial numbers",
+                    serial_numbers.len()
+                );
+                self.records.are_spent(&serial_numbers).map(|result| {
+                    // only spent serial numbers can be proven
+                    let spent_serial_numbers: Vec<vm::Field> = serial_numbers
+                        .iter()
+                        .zip(&result)
+                        .filter(|(_, is_spent)| **is_spent)
+                        .map(|(serial_number, _)| *serial_number)
+                        .collect();
+                    (
+                        bincode::serialize(&result).unwrap(),
+                        vec![],
+                        spent_serial_numbers,
+                    )
+                })
+            }
+            Ok(AbciQuery::GetStateRoots) => {
+                debug!("Fetching state roots");
+                Ok((bincode::serialize(&roots).unwrap(), vec![], vec![]))
+            }
+            Ok(AbciQuery::RevealsInputRecords) => {
+                debug!("Fetching the input records requirement");
+                let reveal = self.reveal_input_records.load(Ordering::SeqCst);
+                Ok((bincode::serialize(&reveal).unwrap(), vec![], vec![]))
+            }
+            Ok(AbciQuery::GetVerificationCacheStats) => {
+                debug!("Fetching verification cache stats");
+                let stats = self.verification_cache.stats();
+                Ok((bincode::serialize(&stats).unwrap(), vec![], vec![]))
+            }
+            Ok(AbciQuery::GetValidators) => {
+                debug!("Fetching validators");
+                // the committed validators are read from the storage, ignoring the changes of the block in progress
+                ValidatorSet::load(&self.storage).and_then(|validator_set| {
+                    let value = serde_json::to_vec(&validator_set.validators())?;
+                    Ok((value, vec![], vec![]))
+                })
+            }
+            Ok(AbciQuery::IsPending { transaction_id }) => {
+                debug!("Checking pending status of {}", transaction_id);
+                let pending = self
+                    .pending_claims
+                    .lock()
+                    .unwrap()
+                    .contains(&transaction_id);
+                Ok((bincode::serialize(&pending).unwrap(), vec![], vec![]))
+            }
+            Ok(AbciQuery::GetProgram { program_id }) => {
+                debug!("Fetching {}", program_id);
+                self.programs.get(&program_id).map(|result| {
+                    let value =
+                        bincode::serialize(&result.map(|(program, _keys)| program)).unwrap();
+                    (value, vec![], vec![])
+                })
+            }
+            Err(e) => Err(AppError::Malformed(format!("invalid query: {e}")).into()),
+        };
+
+        let query_result = query_result.and_then(|(value, commitments, serial_numbers)| {
+            let proof_ops = if request.prove {
+                Some(self.proof_ops(roots, &commitments, &serial_numbers)?)
+            } else {
+                None
+            };
+            Ok((value, proof_ops))
+        });
+
+        match query_result {
+            Ok((value, proof_ops)) => abci::ResponseQuery {
+                value,
+                proof_ops,
+                height,
+                ..Default::default()
+            },
+            Err(e) => query_error(&e),
+        }
+    }
+
+    /// Copy the storage to a temporary directory, to answer a query at the given past height.
+    /// The files of the copy are hard links to the ones of the storage, so this is cheap regardless of the state size.
+    fn checkpoint(&self, height: i64) -> Result<PathBuf> {
+        let (last_height, _) = self.last_block();
+        ensure!(
+            (1..last_height).contains(&height),
+            AppError::UnavailableHeight(format!(
+                "can't query height {height}, the last committed height is {last_height}"
+            ))
+        );
+
+        let id = QUERY_CHECKPOINTS.fetch_add(1, Ordering::Relaxed);
+        let path = self.path.with_extension(format!("query-{id}"));
+        if path.exists() {
+            // left over by a previous run of the node
+            std::fs::remove_dir_all(&path)?;
+        }
+        self.storage.checkpoint(&path)?;
+        Ok(path)
+    }
+
+    /// Return the lowest height to keep after committing the given one, or zero to keep all of them.
+    fn retain_height(&self, height: i64) -> i64 {
+        let retain_blocks = self.settings.retain_blocks as i64;
+        if retain_blocks > 0 && height > retain_blocks {
+            height - retain_blocks + 1
+        } else {
+            0
+        }
+    }
+
+    /// Return the height and state roots of the last committed block, or zero if none was committed yet.
+    fn last_block(&self) -> (i64, Option<StateRoots>) {
+        read_last_block(&self.storage)
+    }
+
+    /// Build the query proof operations for the given commitments and serial numbers, plus one
+    /// with the state roots they are proven against.
+    fn proof_ops(
+        &self,
+        roots: Option<StateRoots>,
+        commitments: &[vm::Field],
+        serial_numbers: &[vm::Field],
+    ) -> Result<ProofOps> {
+        let roots = roots.ok_or_else(|| anyhow!("there's no committed state to prove"))?;
+        let mut ops = vec![ProofOp {
+            r#type: merkle::STATE_ROOTS_PROOF_OP.to_string(),
+            key: vec![],
+            data: bincode::serialize(&roots)?,
+        }];
+
+        let record_proofs = self.records.prove_records(commitments)?;
+        let serial_number_proofs = self.records.prove_serial_numbers(serial_numbers)?;
+        let proofs = commitments
+            .iter()
+            .zip(record_proofs)
+            .map(|(key, proof)| (merkle::COMMITMENT_PROOF_OP, key, proof))
+            .chain(
+                serial_numbers
+                    .iter()
+                    .zip(serial_number_proofs)
+                    .map(|(key, proof)| (merkle::SERIAL_NUMBER_PROOF_OP, key, proof)),
+            );
+
+        for (op_type, key, proof) in proofs {
+            let proof = proof.ok_or_else(|| anyhow!("missing merkle proof for {key}"))?;
+            ops.push(ProofOp {
+                r#type: op_type.to_string(),
+                key: key.to_string().into_bytes(),
+                data: bincode::serialize(&proof)?,
+            });
+        }
+        Ok(ProofOps { ops })
+    }
+
+    /// Fail if the same record appears more than once as a function input in the transaction.
+    fn check_no_duplicate_records(&self, transaction: &Transaction) -> Result<()> {
+        let serial_numbers = transaction.record_serial_numbers();
+        if let Some(serial_number) = serial_numbers.iter().duplicates().next() {
+            bail!(AppError::DuplicateInput(format!(
+                "record with serial number {} in transaction {} is duplicate",
+                serial_number,
+                transaction.id()
+            )));
+        }
+        Ok(())
+    }
+
+    /// the transaction should be rejected if its input records aren't known to be unspent either in the ledger or in an
+    /// unconfirmed transaction. If the chain requires transactions to reveal their input records, they should also exist in
+    /// the ledger as of the state root referenced by the transaction. The link between the revealed record commitments and
+    /// the spent serial numbers is checked when validating the transaction.
+    fn check_inputs_are_unspent(&self, transaction: &Transaction) -> Result<()> {
+        let input_records = transaction.input_records();
+        ensure!(
+            !self.reveal_input_records.load(Ordering::SeqCst)
+                || input_records.records.len() == transaction.record_serial_numbers().len(),
+            AppError::InvalidTransaction(
+                "this chain requires transactions to reveal their input records".to_string()
+            )
+        );
+        for (commitment, _gamma) in &input_records.records {
+            ensure!(
+                self.records.exists(commitment, &input_records.state_root)?,
+                AppError::UnknownInput(format!("input record {commitment} is unknown"))
+            );
+        }
+
+        // a failure reading the store is returned as is, rather than taken as the record being spent
+        for serial_number in transaction.record_serial_numbers() {
+            ensure!(
+                self.records.is_unspent(&serial_number)?,
+                AppError::AlreadySpent(format!(
+                    "input record serial number {serial_number} is already spent"
+                ))
+            );
+        }
+        Ok(())
+    }
+
+    /// Run the stateful validations of a transaction already accepted to the mempool against the committed state,
+    /// skipping the proof verification.
+    fn recheck_transaction(&self, transaction: &Transaction) -> Result<()> {
+        self.check_not_expired(transaction, self.next_height())?;
+        self.check_no_duplicate_records(transaction)?;
+        self.check_inputs_are_unspent(transaction)?;
+        self.check_pending_claims(transaction)?;
+        if let Transaction::Deployment { program, .. } = transaction {
+            self.check_program_is_new(program)?;
+        }
+        Ok(())
+    }
+
+    /// Return the height of the next block, the earliest a transaction checked for the mempool can be included in.
+    fn next_height(&self) -> i64 {
+        self.height.load(Ordering::SeqCst) + 1
+    }
+
+    /// Fail if the transaction can't be included in a block at the given height because it expired.
+    fn check_not_expired(&self, transaction: &Transaction, height: i64) -> Result<()> {
+        if let Some(expires_at_height) = transaction.expires_at_height() {
+            ensure!(
+                height as u64 <= expires_at_height,
+                AppError::Expired(format!(
+                    "Transaction expired at height {expires_at_height}, current height is {height}"
+                ))
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail if the program is already deployed.
+    fn check_program_is_new(&self, program: &vm::Program) -> Result<()> {
+        ensure!(
+            !self.programs.exists(program.id())?,
+            AppError::ProgramExists(format!("Program already exists: {}", program.id()))
+        );
+        Ok(())
+    }
+
+    /// Fail if the input records of the transaction are spent by a transaction already accepted to the mempool,
+    /// unless this one pays a higher fee to replace it.
+    fn check_pending_claims(&self, transaction: &Transaction) -> Result<()> {
+        self.pending_claims.lock().unwrap().check(
+            transaction.id(),
+            transaction.fees(),
+            &transaction.record_serial_numbers(),
+        )
+    }
+
+    /// Mark the input records of a transaction accepted to the mempool as spent by it, until the next block is committed.
+    fn claim_inputs(&self, transaction: &Transaction) {
+        let replaced = self.pending_claims.lock().unwrap().claim(
+            transaction.id(),
+            transaction.fees(),
+            &transaction.record_serial_numbers(),
+        );
+        for replaced_id in replaced {
+            info!("Transaction {replaced_id} replaced by {transaction} with a higher fee");
+        }
+    }
+
+    /// Mark all input records as spent in the record store. This operation could fail if the records are unknown or already spent,
+    /// but it's assumed the that was validated before as to prevent half-applied transactions in the block.
+    fn spend_input_records(&self, transaction: &Transaction) -> Result<()> {
+        transaction
+            .record_serial_numbers()
+            .iter()
+            .map(|serial_number| self.records.spend(serial_number))
+            .find(|result| result.is_err())
+            .unwrap_or(Ok(()))
+    }
+
+    /// Add the tranasction output records as unspent in the record store.
+    fn add_output_records(&self, transaction: &Transaction) -> Result<()> {
+        transaction
+            .output_records()
+            .iter()
+            .map(|(commitment, record)| self.records.add(*commitment, record.clone()))
+            .find(|result| result.is_err())
+            .unwrap_or(Ok(()))
+    }
+
+    /// Apply validator set side-effects of the transaction: collecting fees and changing
+    /// the voting power based on staking transactions.
+    fn update_validators(&self, transaction: &Transaction) -> Result<()> {
+        let mut validator_set = self.validators.lock().unwrap();
+        validator_set.collect(transaction.fees() as u64);
+        transaction
+            .stake_updates()?
+            .into_iter()
+            .for_each(|update| validator_set.apply(update));
+        Ok(())
+    }
+
+    fn validate_transaction(&self, transaction: &Transaction) -> Result<()> {
+        self.check_chain_id(transaction)?;
+        transaction
+            .verify()
+            .map_err(|e| AppError::InvalidTransaction(e.to_string()))?;
+
+        // checked before verifying the proofs, so underpaying transactions don't cost validators the expensive work
+        self.check_minimum_fee(transaction)?;
+
+        let result: Result<()> = match transaction {
+            Transaction::Deployment {
+                ref program,
+                verifying_keys,
+                fee,
+                ..
+            } => {
+                self.check_program_is_new(program)?;
+
+                if let Some(transition) = fee {
+                    self.verify_transitions(std::slice::from_ref(transition))?;
+                }
+
+                // verify deployment is correct and keys are valid
+                vm::verify_deployment(program, verifying_keys.clone())
+                    .map_err(|e| AppError::InvalidProof(e.to_string()).into())
+            }
+            Transaction::Execution { transitions, .. } => {
+                ensure!(
+                    !transitions.is_empty(),
+                    AppError::InvalidTransaction(
+                        "There are no transitions in the execution".to_string()
+                    )
+                );
+
+                let validator_set = self.validators.lock().unwrap();
+                let updates = transaction
+                    .stake_updates()
+                    .map_err(|e| AppError::InvalidStake(e.to_string()))?;
+                for update in updates {
+                    validator_set
+                        .validate(&update)
+                        .map_err(|e| AppError::InvalidStake(e.to_string()))?;
+                }
+
+                self.verify_transitions(transitions)
+            }
+        };
+
+        match result {
+            Err(ref e) => error!("Transaction {} verification failed: {}", transaction, e),
+            _ => info!("Transaction {} verification successful", transaction),
+        };
+        result
+    }
+
+    /// Fail if the transaction is in the legacy encoding. It can still be decoded, but it's not signed by the creators
+    /// of its transitions, so they could have been bundled by anyone.
+    fn check_not_legacy(&self, transaction: &Transaction) -> Result<()> {
+        ensure!(
+            !transaction.is_legacy(),
+            AppError::LegacyTransaction(format!(
+                "Transaction {} is in the legacy encoding and needs to be built again",
+                transaction.id()
+            ))
+        );
+        Ok(())
+    }
+
+    /// Fail if the transaction was created for a different chain, so it can't be replayed from other networks.
+    fn check_chain_id(&self, transaction: &Transaction) -> Result<()> {
+        let chain_id = self.chain_id.read().unwrap();
+        ensure!(
+            transaction.chain_id() == *chain_id,
+            AppError::WrongChain(format!(
+                "Transaction for chain '{}' but this is chain '{chain_id}'",
+                transaction.chain_id()
+            ))
+        );
+        Ok(())
+    }
+
+    /// Fail if the transaction doesn't pay the minimum fee required by the fee schedule.
+    fn check_minimum_fee(&self, transaction: &Transaction) -> Result<()> {
+        let fees = *self.fees.read().unwrap();
+        let minimum_fee = fees.minimum_fee(transaction, |program_id| {
+            self.programs
+                .get(program_id)?
+                .map(|(program, _keys)| program)
+                .ok_or_else(|| {
+                    AppError::UnknownProgram(format!("Program {program_id} does not exist")).into()
+                })
+        })?;
+
+        let paid_fee = transaction.fees();
+        ensure!(
+            paid_fee >= 0,
+            AppError::NegativeFee(format!(
+                "Transaction has a negative fee of {paid_fee} gates"
+            ))
+        );
+        ensure!(
+            paid_fee as u64 >= minimum_fee,
+            AppError::InsufficientFee(format!(
+                "Transaction pays a fee of {paid_fee} gates, below the minimum of {minimum_fee}"
+            ))
+        );
+        Ok(())
+    }
+
+    /// Check the given execution transitions with the verifying keys from the program store.
+    /// Transitions already verified, e.g. when the transaction was checked for the mempool, are skipped,
+    /// and the proofs of the rest are verified in parallel.
+    fn verify_transitions(&self, transitions: &[vm::Transition]) -> Result<()> {
+        let mut pending = Vec::new();
+        let mut program_keys = HashMap::new();
+        for transition in transitions {
+            let cache_key = VerificationCache::key(transition)?;
+            if self.verification_cache.contains(&cache_key) {
+                debug!("Transition {} already verified", transition.id());
+                continue;
+            }
+
+            ensure!(
+                *transition.fee() >= 0,
+                AppError::NegativeFee(format!(
+                    "Transition {} has a negative fee of {} gates",
+                    transition.id(),
+                    transition.fee()
+                ))
+            );
+
+            // the keys are fetched beforehand since the program store can't be shared across the verification threads
+            let program_id = transition.program_id();
+            if let Entry::Vacant(entry) = program_keys.entry(*program_id) {
+                // only verify if we have the program available
+                let (_program, keys) = self.programs.get(program_id)?.ok_or_else(|| {
+                    AppError::UnknownProgram(format!("Program {program_id} does not exist"))
+                })?;
+                entry.insert(keys);
+            }
+            pending.push((transition, cache_key));
+        }
+
+        let cache = &self.verification_cache;
+        pending
+            .into_par_iter()
+            .try_for_each(|(transition, cache_key)| -> Result<()> {
+                vm::verify_execution(transition, &program_keys[transition.program_id()])
+                    .map_err(|e| AppError::InvalidProof(e.to_string()))?;
+                cache.insert(cache_key);
+                Ok(())
+            })
+    }
+
+    fn store_program(&self, transaction: &Transaction) -> Result<()> {
+        if let Transaction::Deployment {
+            program,
+            verifying_keys,
+            ..
+        } = transaction
+        {
+            self.programs.add(program.id(), program, verifying_keys)?
+        }
+        Ok(())
+    }
+}
+
+/// Undo the blocks of the storage committed after the given height, returning how many were rolled back.
+/// Fails without changing the storage if the undo data needed to reach the height was pruned.
+fn rollback_storage(storage: &Storage, height: i64) -> Result<u64> {
+    let (last_height, _) = read_last_block(storage);
+    ensure!(
+        (0..last_height).contains(&height),
+        "can't roll back to height {height}, the last committed height is {last_height}"
+    );
+
+    storage.rollback(height as u64, last_height as u64)
+}
+
+/// The application state at a past height, opened on a copy of the storage rolled back to it to answer queries.
+/// The copy is removed once the state is dropped from the cache and the queries using it are answered.
+#[derive(Debug)]
+struct PastState {
+    height: i64,
+    path: PathBuf,
+    app: Option<SnarkVMApp>,
+}
+
+impl PastState {
+    /// Open the state of the given height on the storage copy at the given path, removing the copy if that fails.
+    fn open(path: PathBuf, height: i64) -> Result<Self> {
+        let mut state = Self {
+            height,
+            path,
+            app: None,
+        };
+        state.app = Some(open_checkpoint(&state.path, height)?);
+        Ok(state)
+    }
+
+    fn run_query(&self, request: abci::RequestQuery) -> abci::ResponseQuery {
+        self.app
+            .as_ref()
+            .expect("past state is open")
+            .run_query(request)
+    }
+}
+
+impl Drop for PastState {
+    fn drop(&mut self) {
+        // the storage needs to be closed before removing its files
+        if let Some(app) = self.app.take() {
+            app.shutdown().unwrap_or_else(|e| error!("{e}"));
+        }
+        std::fs::remove_dir_all(&self.path).unwrap_or_else(|e| error!("{e}"));
+    }
+}
+
+/// Open the application on the storage copy at the given path, rolled back to the given height.
+fn open_checkpoint(path: &Path, height: i64) -> Result<SnarkVMApp> {
+    let storage = Arc::new(Storage::open(path)?);
+    rollback_storage(&storage, height)?;
+    SnarkVMApp::from_storage(
+        storage,
+        path,
+        Snapshots::new(path.join(SNAPSHOTS_PATH), 0, 0),
+        Settings::default(),
+    )
+}
+
+/// Return the amount of records to return for a query with the given limit, capped to the maximum page size.
+fn page_size(limit: Option<usize>) -> usize {
+    limit.map_or(MAX_RECORDS_PAGE_SIZE, |limit| {
+        limit.min(MAX_RECORDS_PAGE_SIZE)
+    })
+}
+
+/// Build the response of a failed query.
+fn query_error(error: &anyhow::Error) -> abci::ResponseQuery {
+    let error = AppError::find(error);
+    abci::ResponseQuery {
+        code: error.code(),
+        log: format!("Error running query: {error}"),
+        info: format!("Error running query: {error}"),
+        codespace: CODESPACE.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Build the response of a transaction rejected by check tx.
+fn check_tx_error(error: &anyhow::Error) -> abci::ResponseCheckTx {
+    let error = AppError::find(error);
+    abci::ResponseCheckTx {
+        code: error.code(),
+        log: format!("Could not verify transaction: {error}"),
+        info: format!("Could not verify transaction: {error}"),
+        codespace: CODESPACE.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Build the response of a transaction that failed to be delivered.
+fn deliver_tx_error(error: &anyhow::Error) -> abci::ResponseDeliverTx {
+    let error = AppError::find(error);
+    abci::ResponseDeliverTx {
+        code: error.code(),
+        log: format!("Error delivering transaction: {error}"),
+        info: format!("Error delivering transaction: {error}"),
+        codespace: CODESPACE.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Build the events of a successfully delivered transaction, indexed by tendermint so it can be searched by its id,
+/// the records it added and spent, the program it deployed or the validators it staked to.
+fn transaction_events(transaction: &Transaction) -> Result<Vec<abci::Event>> {
+    let mut app_event = event(events::TX_ID, transaction.id());
+    app_event
+        .attributes
+        .push(event_attribute(events::FEE, transaction.fees()));
+    let mut result = vec![app_event];
+
+    for (commitment, _) in transaction.output_records() {
+        result.push(event(events::COMMITMENT, commitment));
+    }
+    for serial_number in transaction.record_serial_numbers() {
+        result.push(event(events::SERIAL_NUMBER, serial_number));
+    }
+    if let Transaction::Deployment { program, .. } = transaction {
+        result.push(event(events::PROGRAM_DEPLOYED, program.id()));
+    }
+    for stake in transaction.stake_updates()? {
+        let key = if stake.gates_delta() > 0 {
+            events::STAKE
+        } else {
+            events::UNSTAKE
+        };
+        let mut stake_event = event(key, hex::encode_upper(stake.validator_address()));
+        stake_event.attributes.extend([
+            event_attribute(events::STAKE_AMOUNT, stake.gates_delta().unsigned_abs()),
+            event_attribute(events::STAKE_ALEO_ADDRESS, stake.aleo_address()),
+        ]);
+        result.push(stake_event);
+    }
+    Ok(result)
+}
+
+/// Build an event with a single attribute.
+fn event(key: EventKey, value: impl std::fmt::Display) -> abci::Event {
+    abci::Event {
+        r#type: key.event.to_string(),
+        attributes: vec![event_attribute(key, value)],
+    }
+}
+
+/// Build an event attribute, indexed by tendermint.
+fn event_attribute(key: EventKey, value: impl std::fmt::Display) -> abci::EventAttribute {
+    abci::EventAttribute {
+        key: key.attribute.as_bytes().to_vec(),
+        value: value.to_string().into_bytes(),
+        index: true,
+    }
+}
+
+/// Read the fee schedule set on genesis from the storage, or the default one, without a minimum fee, if there's none.
+fn read_fee_schedule(storage: &Storage) -> Result<FeeSchedule> {
+    match storage.get(Column::Meta, FEE_SCHEDULE_KEY)? {
+        Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+        None => Ok(FeeSchedule::default()),
+    }
+}
+
+/// Read the chain id set on genesis from the storage, or an empty one if there's none.
+fn read_chain_id(storage: &Storage) -> Result<String> {
+    match storage.get(Column::Meta, CHAIN_ID_KEY)? {
+        Some(bytes) => Ok(String::from_utf8(bytes)?),
+        None => Ok(String::new()),
+    }
+}
+
+/// Read whether transactions need to reveal their input records, as set on genesis, or false if it wasn't set.
+fn read_reveal_input_records(storage: &Storage) -> Result<bool> {
+    Ok(storage
+        .get(Column::Meta, REVEAL_INPUT_RECORDS_KEY)?
+        .map(|bytes| bytes == [1])
+        .unwrap_or_default())
+}
+
+/// Read the height and state roots of the last committed block from the storage.
+fn read_last_block(storage: &Storage) -> (i64, Option<StateRoots>) {
+    // if contents are not readable, crash intentionally
+    storage
+        .get(Column::Meta, LAST_BLOCK_KEY)
+        .expect("could not read the last block from the storage")
+        .map(|bytes| bincode::deserialize(&bytes).expect("last block is not readable"))
+        .unwrap_or((0, None))
+}
+
+/// Calculate the hash of the state that can't be proven through queries: the deployed programs, the validator set,
+/// the fee schedule, the chain id and the input records requirement. It covers the whole state rather than the changes
+/// of a block, so it can be checked against the contents of a snapshot.
+fn rest_hash(
+    programs_hash: &[u8],
+    validators: &ValidatorSet,
+    fees: &FeeSchedule,
+    chain_id: &str,
+    reveal_input_records: bool,
+) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(programs_hash);
+    hasher.update(validators.hash());
+    hasher.update(bincode::serialize(fees).unwrap());
+    hasher.update(chain_id);
+    hasher.update([reveal_input_records as u8]);
+    hasher.finalize().into()
+}
+
+// just covering a few special cases here. lower level test are done in record store and program store, higher level in integration tests.
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{AppError, CODESPACE},
+        merkle::StateRoots,
+        query::{AbciQuery, MAX_RECORDS_PAGE_SIZE},
+        transaction::{legacy_bytes, mint, Transaction, MAX_TRANSACTION_SIZE},
+        vm::{self, Identifier},
+    };
+    use rand::Rng;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+    use std::{path::Path, str::FromStr};
+    use tendermint_abci::Application;
+    use tendermint_proto::abci::{
+        CheckTxType, RequestBeginBlock, RequestCheckTx, RequestDeliverTx, RequestInitChain,
+        RequestQuery,
+    };
+    use tendermint_proto::types::Header;
+
+    use super::{Settings, SnarkVMApp};
+    use crate::blockchain::block_verifier::{preverify_transitions, BlockVerifier};
+    use crate::blockchain::snapshots::Snapshots;
+    use crate::blockchain::storage::{Batch, Column};
+
+    const TEST_CHAIN_ID: &str = "test-chain";
+
+    #[test]
+    fn test_abci_hooks() {
+        let app = open_app(
+            "abci",
+            br#"{"records": [], "validators": [], "reveal_input_records": true}"#,
+        );
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let view_key = vm::ViewKey::try_from(&private_key).unwrap();
+        let program = deploy_records_program(&app, &private_key);
+
+        // normal execution to mint a record, validations should succeed
+        let transaction = mint(&private_key, TEST_CHAIN_ID, None);
+        assert!(app.check_tx(check_request(&transaction)).code == 0);
+
+        // the minted record needs to be committed before it can be spent
+        let response = app.deliver_tx(deliver_request(&transaction));
+        assert!(response.code == 0);
+
+        // the transaction is indexed by its id and the commitment of the record it added
+        assert_eq!(
+            transaction.id().as_bytes(),
+            response.events[0].attributes[0].value
+        );
+        let commitment = transaction.output_records()[0].0;
+        assert!(response.events.iter().any(|event| event.r#type == "record"
+            && event.attributes[0].key == b"commitment"
+            && event.attributes[0].value == commitment.to_string().as_bytes()));
+        app.commit();
+        let (height, roots) = app.last_block();
+        assert_eq!(1, height);
+        let state_root = roots.unwrap().commitments;
+        let record = output_record(&transaction, &view_key);
+
+        // utilize the same record twice
+        let consume_two_transaction = Transaction::execution(
+            program.clone(),
+            &[],
+            Identifier::from_str("consume_two").unwrap(),
+            &[record.clone(), record.clone()],
+            &private_key,
+            None,
+            Some(state_root),
+            TEST_CHAIN_ID,
+            None,
+        )
+        .unwrap();
+
+        // both check_tx and deliver_tx validate that inputs are not being spent twice
+        let check_tx_req = check_request(&consume_two_transaction);
+        let deliver_tx_req = deliver_request(&consume_two_transaction);
+        let response = app.check_tx(check_tx_req);
+        assert_eq!(code(AppError::DuplicateInput), response.code);
+        assert_eq!(CODESPACE, response.codespace);
+        let response = app.deliver_tx(deliver_tx_req);
+        assert_eq!(code(AppError::DuplicateInput), response.code);
+
+        // the failed transaction is still indexed by its id
+        assert_eq!(
+            consume_two_transaction.id().as_bytes(),
+            response.events[0].attributes[0].value
+        );
+
+        // transactions that can't be decoded are rejected instead of crashing the app
+        let malformed_req = RequestCheckTx {
+            tx: b"not a transaction".to_vec(),
+            r#type: 0,
+        };
+        assert_eq!(code(AppError::Malformed), app.check_tx(malformed_req).code);
+
+        // because validations failed, inputs should not be spent in the store
+        app.check_inputs_are_unspent(&consume_two_transaction)
+            .unwrap();
+
+        // consume the record
+        let consume_transaction = Transaction::execution(
+            program.clone(),
+            &[],
+            Identifier::from_str("consume").unwrap(),
+            &[record.clone()],
+            &private_key,
+            None,
+            Some(state_root),
+            TEST_CHAIN_ID,
+            None,
+        )
+        .unwrap();
+
+        // this chain requires transactions to reveal their input records
+        let unrevealed_transaction = Transaction::execution(
+            program.clone(),
+            &[],
+            Identifier::from_str("consume").unwrap(),
+            &[record],
+            &private_key,
+            None,
+            None,
+            TEST_CHAIN_ID,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            code(AppError::InvalidTransaction),
+            app.check_tx(check_request(&unrevealed_transaction)).code
+        );
+
+        // the transitions sign the rest of the transaction, so they can't be repackaged in another one
+        let mut repackaged = json!(consume_transaction);
+        repackaged["Execution"]["expires_at_height"] = json!(1000);
+        let repackaged: Transaction = serde_json::from_value(repackaged).unwrap();
+        let repackaged = Transaction::from_bytes(&repackaged.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            code(AppError::InvalidTransaction),
+            app.check_tx(check_request(&repackaged)).code
+        );
+
+        let check_tx_req = check_request(&consume_transaction);
+        let deliver_tx_req = deliver_request(&consume_transaction);
+
+        // because the transaction is valid, check and deliver should succeed
+        assert!(app.check_tx(check_tx_req.clone()).code == 0);
+        assert!(app.deliver_tx(deliver_tx_req.clone()).code == 0);
+
+        // because deliver_tx() spends the records, further validations should fail
+        assert!(app.check_tx(check_tx_req).code != 0);
+        assert!(app.deliver_tx(deliver_tx_req).code != 0);
+
+        // a record that was never added to the ledger can't be spent
+        let undelivered_transaction = mint(&private_key, TEST_CHAIN_ID, None);
+        let fabricated_transaction = Transaction::execution(
+            program,
+            &[],
+            Identifier::from_str("consume").unwrap(),
+            &[output_record(&undelivered_transaction, &view_key)],
+            &private_key,
+            None,
+            Some(state_root),
+            TEST_CHAIN_ID,
+            None,
+        )
+        .unwrap();
+        assert!(app.check_tx(check_request(&fabricated_transaction)).code != 0);
+
+        // a restored state is checked against its roots, including the programs, validators and metadata
+        app.commit();
+        let roots = app.last_block().1.unwrap();
+        app.load_restored_state(roots).unwrap();
+        let mut batch = Batch::default();
+        batch.put(Column::Meta, super::CHAIN_ID_KEY, "other-chain");
+        app.storage.write(batch).unwrap();
+        assert!(app.load_restored_state(roots).is_err());
+    }
+
+    #[test]
+    fn reject_other_chain_transactions() {
+        let app = open_app("abci_chain_id", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        deploy_records_program(&app, &private_key);
+
+        // the same transaction built for another chain is rejected
+        let transaction = mint(&private_key, "other-chain", None);
+        assert_eq!(
+            code(AppError::WrongChain),
+            app.check_tx(check_request(&transaction)).code
+        );
+        assert_eq!(
+            code(AppError::WrongChain),
+            app.deliver_tx(deliver_request(&transaction)).code
+        );
+    }
+
+    #[test]
+    fn reject_legacy_transactions() {
+        let app = open_app("abci_legacy", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        deploy_records_program(&app, &private_key);
+
+        // legacy transactions are decoded, but rejected before any other check since they aren't signed
+        let tx = legacy_bytes(&mint(&private_key, TEST_CHAIN_ID, None));
+        let response = app.check_tx(RequestCheckTx {
+            tx: tx.clone(),
+            r#type: 0,
+        });
+        assert_eq!(code(AppError::LegacyTransaction), response.code);
+        assert_eq!(
+            code(AppError::LegacyTransaction),
+            app.deliver_tx(RequestDeliverTx { tx }).code
+        );
+    }
+
+    #[test]
+    fn reject_expired_transactions() {
+        let app = open_app("abci_expiry", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        deploy_records_program(&app, &private_key);
+
+        // a transaction can be included up to its expiry height, but not after it
+        let transaction = mint(&private_key, TEST_CHAIN_ID, Some(1));
+        assert_eq!(0, app.check_tx(check_request(&transaction)).code);
+        let expired_transaction = mint(&private_key, TEST_CHAIN_ID, Some(0));
+        assert_eq!(
+            code(AppError::Expired),
+            app.check_tx(check_request(&expired_transaction)).code
+        );
+    }
+
+    #[test]
+    fn keep_conflicting_transactions_out_of_mempool() {
+        let app = open_app("abci_pending", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let view_key = vm::ViewKey::try_from(&private_key).unwrap();
+        let program = deploy_records_program(&app, &private_key);
+
+        let minted = mint(&private_key, TEST_CHAIN_ID, None);
+        let other_minted = mint(&private_key, TEST_CHAIN_ID, None);
+        assert_eq!(0, app.deliver_tx(deliver_request(&minted)).code);
+        app.commit();
+        let state_root = app.last_block().1.unwrap().commitments;
+        let consume = |record| {
+            Transaction::execution(
+                program.clone(),
+                &[],
+                Identifier::from_str("consume").unwrap(),
+                &[record],
+                &private_key,
+                None,
+                Some(state_root),
+                TEST_CHAIN_ID,
+                None,
+            )
+            .unwrap()
+        };
+
+        let consume_transaction = consume(output_record(&minted, &view_key));
+        assert_eq!(0, app.check_tx(check_request(&consume_transaction)).code);
+        assert!(is_pending(&app, &consume_transaction));
+
+        // another transaction spending the same record, without paying a higher fee, is kept out of the mempool
+        let conflicting_transaction = consume(output_record(&minted, &view_key));
+        assert_eq!(
+            code(AppError::PendingConflict),
+            app.check_tx(check_request(&conflicting_transaction)).code
+        );
+
+        // once the block is committed, rechecking the mempool evicts the transactions spending the same records
+        // but keeps the ones that are still valid
+        assert_eq!(0, app.check_tx(check_request(&other_minted)).code);
+        assert_eq!(
+            0,
+            app.deliver_tx(deliver_request(&consume_transaction)).code
+        );
+        app.commit();
+        assert!(!is_pending(&app, &consume_transaction));
+        assert!(app.check_tx(recheck_request(&consume_transaction)).code != 0);
+        assert!(app.check_tx(recheck_request(&other_minted)).code == 0);
+        assert!(is_pending(&app, &other_minted));
+    }
+
+    #[test]
+    fn query_past_heights() {
+        let app = open_app("abci_past_heights", br#"{"records": [], "validators": []}"#);
+        app.commit();
+        let (_, roots) = app.last_block();
+        app.commit();
+
+        // past heights are answered with a copy of the state rolled back to them
+        let query = |height| {
+            app.query(RequestQuery {
+                data: AbciQuery::GetStateRoots.into(),
+                height,
+                ..Default::default()
+            })
+        };
+        let response = query(1);
+        assert_eq!(0, response.code);
+        assert_eq!(1, response.height);
+        let past_roots: Option<StateRoots> = bincode::deserialize(&response.value).unwrap();
+        assert_eq!(roots, past_roots);
+
+        // the past state is kept open for the following queries at the same height
+        assert_eq!(0, query(1).code);
+        assert_eq!(1, app.past_states.lock().unwrap().len());
+
+        // heights that weren't committed yet can't be queried
+        assert_eq!(code(AppError::UnavailableHeight), query(3).code);
+
+        // the verification cache and pending transactions are only known at the last height
+        for data in [
+            AbciQuery::GetVerificationCacheStats,
+            AbciQuery::IsPending {
+                transaction_id: "id".to_string(),
+            },
+        ] {
+            let query = |height| {
+                app.query(RequestQuery {
+                    data: data.clone().into(),
+                    height,
+                    ..Default::default()
+                })
+            };
+            assert_eq!(0, query(0).code);
+            assert_eq!(0, query(2).code);
+            assert_eq!(code(AppError::UnavailableHeight), query(1).code);
+        }
+    }
+
+    #[test]
+    fn check_tx_rejects_malformed_transactions() {
+        let app = open_app("abci_malformed", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let transaction = mint(&private_key, TEST_CHAIN_ID, None);
+        let bytes = transaction.to_bytes().unwrap();
+
+        // oversized transactions are rejected before decoding them
+        let response = app.check_tx(RequestCheckTx {
+            tx: vec![0; MAX_TRANSACTION_SIZE + 1],
+            r#type: 0,
+        });
+        assert_eq!(code(AppError::TransactionTooLarge), response.code);
+
+        // truncated transactions, or with trailing bytes, can't be decoded
+        let response = app.check_tx(RequestCheckTx {
+            tx: bytes[..bytes.len() - 1].to_vec(),
+            r#type: 0,
+        });
+        assert_eq!(code(AppError::Malformed), response.code);
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let response = app.check_tx(RequestCheckTx {
+            tx: trailing,
+            r#type: 0,
+        });
+        assert_eq!(code(AppError::Malformed), response.code);
+
+        // randomly corrupted transactions are rejected without panicking, by either failing to decode or to verify
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let mut corrupted = bytes.clone();
+            for _ in 0..rng.gen_range(1..8) {
+                let index = rng.gen_range(0..corrupted.len());
+                corrupted[index] ^= rng.gen_range(1..=u8::MAX);
+            }
+            if corrupted == bytes {
+                continue;
+            }
+            let response = app.check_tx(RequestCheckTx {
+                tx: corrupted,
+                r#type: 0,
+            });
+            assert_ne!(0, response.code);
+        }
+        for _ in 0..200 {
+            let length = rng.gen_range(0..256);
+            let garbage: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            let response = app.check_tx(RequestCheckTx {
+                tx: garbage,
+                r#type: 0,
+            });
+            assert_ne!(0, response.code);
+        }
+    }
+
+    #[test]
+    fn preverify_block_transitions() {
+        let app = open_app("abci_preverify", br#"{"records": [], "validators": []}"#);
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let first = mint(&private_key, TEST_CHAIN_ID, None);
+        let second = mint(&private_key, TEST_CHAIN_ID, None);
+
+        // the transitions of programs that aren't deployed yet are left to be verified when delivered
+        preverify_transitions(&app.programs, &app.verification_cache, first.transitions());
+        assert_eq!(0, app.verification_cache.stats().size);
+
+        deploy_records_program(&app, &private_key);
+
+        // the transitions of all the transactions are verified up front
+        let mut transitions = first.transitions();
+        transitions.extend(second.transitions());
+        let before = app.verification_cache.stats();
+        preverify_transitions(&app.programs, &app.verification_cache, transitions);
+        let stats = app.verification_cache.stats();
+        assert_eq!(2, stats.size);
+
+        // looking them up in the cache beforehand isn't counted as a hit or miss
+        assert_eq!((before.hits, before.misses), (stats.hits, stats.misses));
+
+        // so delivering the transactions doesn't verify the proofs again
+        assert_eq!(0, app.deliver_tx(deliver_request(&first)).code);
+        assert_eq!(0, app.deliver_tx(deliver_request(&second)).code);
+        assert_eq!(stats.hits + 2, app.verification_cache.stats().hits);
+        assert_eq!(stats.misses, app.verification_cache.stats().misses);
+        assert_eq!(stats.size, app.verification_cache.stats().size);
+    }
+
+    #[test]
+    fn preverify_fetched_blocks() {
+        let mut app = open_app(
+            "abci_preverify_fetch",
+            br#"{"records": [], "validators": []}"#,
+        );
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        deploy_records_program(&app, &private_key);
+        let transactions = vec![
+            mint(&private_key, TEST_CHAIN_ID, None),
+            mint(&private_key, TEST_CHAIN_ID, None),
+        ];
+
+        // the block is served as the tendermint RPC would, after failing a couple of times as it does while starting
+        let block: Vec<Vec<u8>> = transactions
+            .iter()
+            .map(|transaction| transaction.to_bytes().unwrap())
+            .collect();
+        let hash = vec![1; 32];
+        let block_hash = hash.clone();
+        let mut attempts = 0;
+        let fetch = move |height: i64, hash: &[u8]| {
+            attempts += 1;
+            anyhow::ensure!(attempts > 2, "the rpc is not available yet");
+            anyhow::ensure!(height == 1 && hash == block_hash, "unknown block");
+            Ok(block.clone())
+        };
+        app.block_verifier = Some(BlockVerifier::start(
+            fetch,
+            app.programs.clone(),
+            app.verification_cache.clone(),
+        ));
+
+        // beginning the block verifies the proofs of all its transitions in the background
+        app.begin_block(RequestBeginBlock {
+            hash,
+            header: Some(Header {
+                height: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let started = Instant::now();
+        while app.verification_cache.stats().size < 2 {
+            assert!(
+                started.elapsed() < Duration::from_secs(120),
+                "the block transitions weren't verified in the background"
+            );
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // so delivering its transactions doesn't verify the proofs again
+        let stats = app.verification_cache.stats();
+        for transaction in &transactions {
+            assert_eq!(0, app.deliver_tx(deliver_request(transaction)).code);
+        }
+        assert_eq!(stats.hits + 2, app.verification_cache.stats().hits);
+    }
+
+    #[test]
+    fn records_page_size() {
+        assert_eq!(MAX_RECORDS_PAGE_SIZE, super::page_size(None));
+        assert_eq!(10, super::page_size(Some(10)));
+        assert_eq!(MAX_RECORDS_PAGE_SIZE, super::page_size(Some(usize::MAX)));
+    }
+
+    /// Open an app in a test directory with the given name, initialized with the given genesis app state.
+    fn open_app(name: &str, app_state: &[u8]) -> SnarkVMApp {
+        let app = SnarkVMApp::open(
+            &Path::new(".db_test").join(name),
+            Snapshots::new(format!(".db_test/{name}_snapshots").into(), 0, 0),
+            Settings::default(),
+        )
+        .unwrap();
+        app.init_chain(RequestInitChain {
+            chain_id: TEST_CHAIN_ID.to_string(),
+            app_state_bytes: app_state.to_vec(),
+            ..Default::default()
+        });
+        app
+    }
+
+    /// Deploy the records program to the app, returning it.
+    fn deploy_records_program(app: &SnarkVMApp, private_key: &vm::PrivateKey) -> vm::Program {
+        let deployment = Transaction::deployment(
+            Path::new("aleo/records.aleo"),
+            &[],
+            private_key,
+            None,
+            None,
+            TEST_CHAIN_ID,
+            None,
+        )
+        .unwrap();
+        app.store_program(&deployment).unwrap();
+        vm::generate_program(include_str!("../../../aleo/records.aleo")).unwrap()
+    }
+
+    /// Return the abci response code of the given kind of error.
+    fn code(error: fn(String) -> AppError) -> u32 {
+        error(String::new()).code()
+    }
+
+    /// Decrypt the first record output by the transaction, to be spent by another one.
+    fn output_record(transaction: &Transaction, view_key: &vm::ViewKey) -> vm::Value {
+        transaction.output_records()[0]
+            .1
+            .decrypt(view_key)
+            .map(vm::Value::Record)
+            .unwrap()
+    }
+
+    fn check_request(transaction: &Transaction) -> RequestCheckTx {
+        RequestCheckTx {
+            tx: transaction.to_bytes().unwrap(),
+            r#type: 0,
+        }
+    }
+
+    fn recheck_request(transaction: &Transaction) -> RequestCheckTx {
+        RequestCheckTx {
+            tx: transaction.to_bytes().unwrap(),
+            r#type: CheckTxType::Recheck as i32,
+        }
+    }
+
+    fn deliver_request(transaction: &Transaction) -> RequestDeliverTx {
+        RequestDeliverTx {
+            tx: transaction.to_bytes().unwrap(),
+        }
+    }
+
+    fn is_pending(app: &SnarkVMApp, transaction: &Transaction) -> bool {
+        let response = app.query(RequestQuery {
+            data: AbciQuery::IsPending {
+                transaction_id: transaction.id().to_string(),
+            }
+            .into(),
+            ..Default::default()
+        });
+        bincode::deserialize(&response.value).unwrap()
+    }
+}
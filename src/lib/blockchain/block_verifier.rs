@@ -0,0 +1,203 @@
+use crate::blockchain::program_store::ProgramStore;
+use crate::blockchain::verification_cache::VerificationCache;
+use crate::{transaction::Transaction, vm};
+use anyhow::{ensure, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tendermint::block::Height;
+use tendermint_rpc::{Client, HttpClient};
+use tracing::{debug, error, info, warn};
+
+/// How long to wait for each request to the tendermint RPC to return a block, so a stuck request doesn't keep the
+/// verifier from retrying or moving on to the next block.
+const BLOCK_FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait before fetching a block again after failing to, unless the next block begins meanwhile.
+const BLOCK_FETCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Verifies the proofs of the transactions of each block that begins in a background thread, so they are found in the
+/// verification cache when each one is delivered. Tendermint 0.34 sends the transactions of a block one at a time, but
+/// it saves the block to its block store before executing it, so it can be fetched from the RPC of the node.
+/// This is only an optimization: the consensus connection doesn't wait for it, and the transactions that weren't
+/// verified in advance by the time they are delivered are verified then. Blocks that can't be fetched, e.g. while
+/// tendermint replays blocks on startup before its RPC is up, are fetched again until the next block begins, and
+/// the failures are logged as warnings.
+#[derive(Clone, Debug)]
+pub struct BlockVerifier {
+    /// Channel used to send the height and hash of each block that begins to the thread that verifies them.
+    /// The thread stops when every copy of the sender is dropped.
+    block_sender: Sender<(i64, Vec<u8>)>,
+}
+
+impl BlockVerifier {
+    /// Start verifying, on a new thread, the blocks fetched from the tendermint RPC at the given url, with the verifying
+    /// keys of the given program store.
+    pub fn new(url: &str, programs: ProgramStore, cache: Arc<VerificationCache>) -> Result<Self> {
+        // the application hooks are synchronous, so the client runs in a runtime of its own, reused for every block
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let client = HttpClient::new(url)?;
+        let fetch = move |height: i64, hash: &[u8]| {
+            runtime.block_on(fetch_block_transactions(&client, height, hash))
+        };
+        Ok(Self::start(fetch, programs, cache))
+    }
+
+    /// Start verifying, on a new thread, the blocks whose transactions are returned by the given function when called
+    /// with their height and hash.
+    pub fn start(
+        mut fetch: impl FnMut(i64, &[u8]) -> Result<Vec<Vec<u8>>> + Send + 'static,
+        programs: ProgramStore,
+        cache: Arc<VerificationCache>,
+    ) -> Self {
+        let (block_sender, block_receiver): (Sender<(i64, Vec<u8>)>, Receiver<(i64, Vec<u8>)>) =
+            channel();
+
+        thread::spawn(move || {
+            let mut failing = false;
+            let mut next = block_receiver.recv().ok();
+            while let Some(mut block) = next.take() {
+                // if the verification fell behind, the blocks that began since are skipped in favor of the last one,
+                // since the transactions of the others are already being delivered
+                while let Ok(next_block) = block_receiver.try_recv() {
+                    block = next_block;
+                }
+                let (height, hash) = &block;
+                match fetch(*height, hash) {
+                    Ok(transactions) => {
+                        if failing {
+                            info!("Fetched block {height} to verify its proofs in advance");
+                            failing = false;
+                        }
+                        let transitions = transactions
+                            .iter()
+                            .filter_map(|bytes| Transaction::from_bytes(bytes).ok())
+                            .flat_map(|transaction| transaction.transitions())
+                            .collect();
+                        preverify_transitions(&programs, &cache, transitions);
+                        next = block_receiver.recv().ok();
+                    }
+                    Err(err) => {
+                        // only the first failure is logged, so an unreachable RPC doesn't flood the logs
+                        if !failing {
+                            warn!(
+                                "Could not fetch block {height} to verify its proofs in advance, retrying until \
+                                 the next block begins: {err}"
+                            );
+                            failing = true;
+                        }
+                        next = match block_receiver.recv_timeout(BLOCK_FETCH_RETRY_DELAY) {
+                            Ok(next_block) => Some(next_block),
+                            Err(RecvTimeoutError::Timeout) => Some(block),
+                            Err(RecvTimeoutError::Disconnected) => None,
+                        };
+                    }
+                }
+            }
+        });
+
+        Self { block_sender }
+    }
+
+    /// Queue the block with the given height and hash to be verified in the background, without waiting for it.
+    pub fn verify(&self, height: i64, hash: &[u8]) {
+        self.block_sender
+            .send((height, hash.to_vec()))
+            .unwrap_or_else(|e| error!("{}", e));
+    }
+}
+
+/// Verify the proofs of the given transitions in parallel, across all of them, and remember the valid ones in the
+/// verification cache. Failures are ignored, since the transitions are verified again, and their transaction
+/// rejected, when it's delivered.
+/// Each transition has a proof of its own, and SnarkVM can only batch verify instances proven together in a
+/// single proof, so the proofs sharing a verifying key aren't batch verified; they share the key fetched from the
+/// program store instead.
+pub fn preverify_transitions(
+    programs: &ProgramStore,
+    cache: &VerificationCache,
+    transitions: Vec<vm::Transition>,
+) {
+    let mut pending = Vec::new();
+    let mut program_keys: HashMap<vm::ProgramID, Option<vm::VerifyingKeyMap>> = HashMap::new();
+    for transition in transitions {
+        let cache_key = match VerificationCache::key(&transition) {
+            Ok(cache_key) => cache_key,
+            Err(_) => continue,
+        };
+        if cache.peek(&cache_key) {
+            continue;
+        }
+
+        // programs deployed in the same block aren't stored yet, their executions are verified when delivered.
+        // The missing programs are remembered too, so the store is asked about each program only once
+        let keys = program_keys
+            .entry(*transition.program_id())
+            .or_insert_with_key(|program_id| {
+                programs
+                    .get(program_id)
+                    .ok()
+                    .flatten()
+                    .map(|(_program, keys)| keys)
+            });
+        if keys.is_some() {
+            pending.push((transition, cache_key));
+        }
+    }
+
+    debug!("Verifying {} transitions in advance", pending.len());
+    pending.into_par_iter().for_each(|(transition, cache_key)| {
+        if let Some(keys) = &program_keys[transition.program_id()] {
+            if vm::verify_execution(&transition, keys).is_ok() {
+                cache.insert(cache_key);
+            }
+        }
+    });
+}
+
+/// Fetch the transactions of the block at the given height from the tendermint RPC, checking it's the one with the
+/// given hash.
+async fn fetch_block_transactions(
+    client: &HttpClient,
+    height: i64,
+    hash: &[u8],
+) -> Result<Vec<Vec<u8>>> {
+    let response =
+        tokio::time::timeout(BLOCK_FETCH_TIMEOUT, client.block(Height::try_from(height)?))
+            .await??;
+    ensure!(
+        response.block_id.hash.as_bytes() == hash,
+        "the block at height {height} doesn't have the expected hash"
+    );
+    Ok(response
+        .block
+        .data
+        .iter()
+        .map(|transaction| transaction.as_bytes().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fetch_block_transactions;
+    use tendermint_rpc::HttpClient;
+
+    #[test]
+    fn unreachable_rpc() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let client = HttpClient::new("http://127.0.0.1:1").unwrap();
+
+        // if the block can't be fetched, its transactions are left to be verified when delivered
+        assert!(runtime
+            .block_on(fetch_block_transactions(&client, 1, &[]))
+            .is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::error::AppError;
+use crate::vm;
+use anyhow::{ensure, Result};
+use std::collections::HashMap;
+
+/// The number of commits a replaced transaction is remembered for. Tendermint rechecks the whole mempool after every
+/// commit, so a replaced transaction still in the mempool is evicted by the recheck after the first one; if it's still
+/// remembered after the second, it already left the mempool without being rechecked, e.g. because it was full.
+const REPLACED_COMMITS: u8 = 2;
+
+/// Keeps track of the record serial numbers spent by the transactions accepted to the mempool but not yet committed,
+/// so two transactions spending the same record can't both get into the mempool (and later into a block, where the
+/// second one would fail after taking up block space).
+/// A transaction conflicting with a pending one is only accepted if it pays a higher fee, in which case it replaces
+/// the pending one: the claims of the replaced transaction are dropped, and it's rejected the next time it's checked,
+/// so tendermint evicts it from the mempool on the recheck that follows the next commit.
+/// The claims are cleared when a block is committed, and set again as tendermint rechecks the remaining transactions.
+#[derive(Debug, Default)]
+pub struct PendingClaims {
+    /// The pending transaction claiming each serial number.
+    claims: HashMap<vm::Field, Claim>,
+    /// The serial numbers claimed by each pending transaction.
+    by_transaction: HashMap<String, Vec<vm::Field>>,
+    /// The transactions replaced by others paying a higher fee, which may still be in the mempool, along with the
+    /// number of commits since they were replaced. Unlike the claims, they are kept across commits: the mempool may
+    /// recheck the replaced transaction before its replacement, so it would otherwise claim its inputs again.
+    replaced: HashMap<String, u8>,
+}
+
+#[derive(Debug, Clone)]
+struct Claim {
+    transaction_id: String,
+    fee: i64,
+}
+
+impl PendingClaims {
+    /// Fail if any of the serial numbers is claimed by another pending transaction paying the same or a higher fee,
+    /// or if the transaction was already replaced by another one.
+    pub fn check(
+        &self,
+        transaction_id: &str,
+        fee: i64,
+        serial_numbers: &[vm::Field],
+    ) -> Result<()> {
+        ensure!(
+            !self.replaced.contains_key(transaction_id),
+            AppError::PendingConflict(format!(
+                "transaction {transaction_id} was replaced by a pending transaction with a higher fee"
+            ))
+        );
+        for serial_number in serial_numbers {
+            if let Some(claim) = self.claims.get(serial_number) {
+                ensure!(
+                    claim.transaction_id == transaction_id || claim.fee < fee,
+                    AppError::PendingConflict(format!(
+                        "input record serial number {} is already spent by pending transaction {} with fee {}",
+                        serial_number, claim.transaction_id, claim.fee
+                    ))
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Claim the serial numbers for the given transaction, replacing the pending transactions that conflict with it.
+    /// Returns the ids of the replaced transactions.
+    pub fn claim(
+        &mut self,
+        transaction_id: &str,
+        fee: i64,
+        serial_numbers: &[vm::Field],
+    ) -> Vec<String> {
+        self.release(transaction_id);
+        let mut replaced = vec![];
+        for serial_number in serial_numbers {
+            if let Some(claim) = self.claims.get(serial_number) {
+                let replaced_id = claim.transaction_id.clone();
+                self.release(&replaced_id);
+                self.replaced.insert(replaced_id.clone(), 0);
+                replaced.push(replaced_id);
+            }
+        }
+
+        for serial_number in serial_numbers {
+            self.claims.insert(
+                *serial_number,
+                Claim {
+                    transaction_id: transaction_id.to_string(),
+                    fee,
+                },
+            );
+        }
+        self.by_transaction
+            .insert(transaction_id.to_string(), serial_numbers.to_vec());
+        replaced
+    }
+
+    /// Return whether the given transaction was accepted to the mempool since the last commit and wasn't replaced.
+    pub fn contains(&self, transaction_id: &str) -> bool {
+        self.by_transaction.contains_key(transaction_id)
+    }
+
+    /// Drop all the claims, e.g. after a block is committed. The replaced transactions are kept until they leave the
+    /// mempool, or until they are known to have left it because they weren't rechecked after a commit.
+    pub fn clear(&mut self) {
+        self.claims.clear();
+        self.by_transaction.clear();
+        self.replaced.retain(|_, commits| {
+            *commits += 1;
+            *commits < REPLACED_COMMITS
+        });
+    }
+
+    /// Forget a replaced transaction that left the mempool, because it was evicted when rechecked or included in a block.
+    pub fn remove_replaced(&mut self, transaction_id: &str) {
+        self.replaced.remove(transaction_id);
+    }
+
+    fn release(&mut self, transaction_id: &str) {
+        for serial_number in self
+            .by_transaction
+            .remove(transaction_id)
+            .unwrap_or_default()
+        {
+            self.claims.remove(&serial_number);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn replace_by_fee() {
+        let one = vm::Field::from_str("1field").unwrap();
+        let two = vm::Field::from_str("2field").unwrap();
+        let three = vm::Field::from_str("3field").unwrap();
+
+        let mut claims = PendingClaims::default();
+        claims.check("tx1", 10, &[one, two]).unwrap();
+        assert!(claims.claim("tx1", 10, &[one, two]).is_empty());
+        assert!(claims.contains("tx1"));
+
+        // the same transaction doesn't conflict with itself
+        claims.check("tx1", 10, &[one, two]).unwrap();
+
+        // a conflicting transaction needs to pay a higher fee
+        assert!(claims.check("tx2", 10, &[two, three]).is_err());
+        claims.check("tx2", 11, &[two, three]).unwrap();
+        assert_eq!(vec!["tx1"], claims.claim("tx2", 11, &[two, three]));
+
+        // the claims of the replaced transaction are released
+        assert!(!claims.contains("tx1"));
+        claims.check("tx3", 0, &[one]).unwrap();
+        assert!(claims.check("tx3", 11, &[three]).is_err());
+
+        // the replaced transaction can't claim its inputs again, even after a commit clears the claims
+        assert!(claims.check("tx1", 10, &[one, two]).is_err());
+        claims.clear();
+        assert!(!claims.contains("tx2"));
+        claims.check("tx3", 0, &[two, three]).unwrap();
+        assert!(claims.check("tx1", 10, &[one, two]).is_err());
+
+        // until it leaves the mempool
+        claims.remove_replaced("tx1");
+        claims.check("tx1", 10, &[one, two]).unwrap();
+    }
+
+    #[test]
+    fn forget_replaced_after_commits() {
+        let one = vm::Field::from_str("1field").unwrap();
+
+        let mut claims = PendingClaims::default();
+        claims.claim("tx1", 10, &[one]);
+        assert_eq!(vec!["tx1"], claims.claim("tx2", 11, &[one]));
+
+        // the replaced transaction is remembered until the mempool is rechecked after the next commit
+        claims.clear();
+        assert!(claims.check("tx1", 10, &[one]).is_err());
+
+        // if it wasn't rechecked and evicted by then, it already left the mempool some other way
+        claims.clear();
+        claims.check("tx1", 10, &[one]).unwrap();
+    }
+}
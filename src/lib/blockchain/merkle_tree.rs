@@ -0,0 +1,331 @@
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::error::AppError;
+use crate::merkle::{self, Hash, MerkleProof, DEPTH};
+use anyhow::{anyhow, ensure, Result};
+use rocksdb::{Direction, IteratorMode};
+use std::collections::{HashMap, VecDeque};
+
+/// Number of past roots of the tree that are accepted to check leaf inclusion against.
+const RECENT_ROOTS: usize = 100;
+
+/// An append-only merkle tree of fixed depth, persisted in the merkle column of the application storage.
+/// Only non-empty nodes are stored, with keys prefixed by a tree identifier so several trees can
+/// share the same column. Besides the nodes, the tree keeps an index from leaf ids (e.g. record commitments)
+/// to their position, to be able to produce proofs for them, and the most recent roots with the size of the
+/// tree at each of them, to be able to tell if a leaf was included in a past version of the tree.
+#[derive(Debug)]
+pub struct MerkleTree {
+    prefix: u8,
+    size: u64,
+    recent_roots: VecDeque<(Hash, u64)>,
+    empty_hashes: Vec<Hash>,
+}
+
+impl MerkleTree {
+    /// Load the tree identified by the given prefix from the storage.
+    pub fn load(storage: &Storage, prefix: u8) -> Result<Self> {
+        let size = match storage.get(Column::Merkle, [prefix, b's'])? {
+            Some(bytes) => u64::from_be_bytes(
+                bytes
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid merkle tree size"))?,
+            ),
+            None => 0,
+        };
+        let recent_roots = match storage.get(Column::Merkle, [prefix, b'r'])? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => VecDeque::new(),
+        };
+
+        Ok(Self {
+            prefix,
+            size,
+            recent_roots,
+            empty_hashes: merkle::empty_hashes(),
+        })
+    }
+
+    /// Return the amount of leaves in the tree.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Return the current root of the tree.
+    pub fn root(&self, storage: &Storage) -> Result<Hash> {
+        self.node(storage, &HashMap::new(), DEPTH, 0)
+    }
+
+    /// Append the given (id, leaf data) entries to the tree, writing the updated nodes to the batch, and return the new root.
+    /// The in-memory tree size is updated, so the batch is expected to be written right after this call.
+    pub fn append(
+        &mut self,
+        storage: &Storage,
+        entries: &[(Vec<u8>, Vec<u8>)],
+        batch: &mut Batch,
+    ) -> Result<Hash> {
+        // keep track of the nodes updated during this call since they aren't in the db yet
+        let mut updated = HashMap::new();
+
+        for (id, leaf) in entries {
+            let mut index = self.size;
+            batch.put(Column::Merkle, self.index_key(id), index.to_be_bytes());
+            updated.insert((0, index), merkle::leaf_hash(leaf));
+
+            for level in 0..DEPTH {
+                let left = self.node(storage, &updated, level, index & !1)?;
+                let right = self.node(storage, &updated, level, index | 1)?;
+                index /= 2;
+                updated.insert((level + 1, index), merkle::node_hash(&left, &right));
+            }
+            self.size += 1;
+        }
+
+        let root = match updated.get(&(DEPTH, 0)) {
+            Some(root) => *root,
+            None => self.root(storage)?,
+        };
+        if self.recent_roots.back().map(|(last_root, _)| last_root) != Some(&root) {
+            self.recent_roots.push_back((root, self.size));
+            if self.recent_roots.len() > RECENT_ROOTS {
+                self.recent_roots.pop_front();
+            }
+            batch.put(
+                Column::Merkle,
+                [self.prefix, b'r'],
+                bincode::serialize(&self.recent_roots)?,
+            );
+        }
+
+        for ((level, index), hash) in updated {
+            batch.put(Column::Merkle, self.node_key(level, index), hash);
+        }
+        batch.put(Column::Merkle, [self.prefix, b's'], self.size.to_be_bytes());
+        Ok(root)
+    }
+
+    /// Return the merkle path of the leaf with the given id, if it's part of the tree.
+    pub fn proof(&self, storage: &Storage, id: &[u8]) -> Result<Option<MerkleProof>> {
+        let index = match self.index(storage, id)? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let mut siblings = Vec::with_capacity(DEPTH);
+        let mut current = index;
+        for level in 0..DEPTH {
+            siblings.push(self.node(storage, &HashMap::new(), level, current ^ 1)?);
+            current /= 2;
+        }
+        Ok(Some(MerkleProof { index, siblings }))
+    }
+
+    /// Return whether the leaf with the given id was already part of the tree when it had the given root.
+    /// Fails if the root is unknown or not among the most recent ones, which can't change by retrying later.
+    pub fn contains(&self, storage: &Storage, id: &[u8], root: &Hash) -> Result<bool> {
+        let size = self
+            .recent_roots
+            .iter()
+            .find(|(recent_root, _)| recent_root == root)
+            .map(|(_, size)| *size)
+            .ok_or_else(|| {
+                AppError::StaleStateRoot(format!(
+                    "unknown or outdated state root {}",
+                    hex::encode(root)
+                ))
+            })?;
+        Ok(self.index(storage, id)?.map_or(false, |index| index < size))
+    }
+
+    /// Recalculate the nodes of the tree from its leaves, checking them against the recent roots, and return the resulting
+    /// root along with a batch that overwrites the stored nodes. Used to verify a tree restored from an untrusted snapshot.
+    /// The leaf data is obtained from the leaf ids with the given function. When it returns None (e.g. because the leaf data
+    /// was pruned), the stored leaf hash is used instead, which is still checked through the recent roots.
+    pub fn rebuild(
+        &self,
+        storage: &Storage,
+        leaf: impl Fn(&[u8]) -> Result<Option<Vec<u8>>>,
+    ) -> Result<(Hash, Batch)> {
+        let prefix = [self.prefix, b'i'];
+        let mut leaves = Vec::with_capacity(self.size as usize);
+        for entry in storage
+            .iterator(
+                Column::Merkle,
+                IteratorMode::From(&prefix, Direction::Forward),
+            )?
+            .take_while(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&prefix))
+            })
+        {
+            let (key, index) = entry?;
+            let index = u64::from_be_bytes(index.as_ref().try_into()?);
+            let hash = match leaf(&key[prefix.len()..])? {
+                Some(data) => merkle::leaf_hash(&data),
+                None => self.node(storage, &HashMap::new(), 0, index)?,
+            };
+            leaves.push((index, hash));
+        }
+        leaves.sort();
+        ensure!(
+            leaves.len() as u64 == self.size
+                && leaves
+                    .iter()
+                    .enumerate()
+                    .all(|(position, (index, _))| position as u64 == *index),
+            "merkle tree leaf indexes are inconsistent"
+        );
+        let leaves: Vec<Hash> = leaves.into_iter().map(|(_, hash)| hash).collect();
+
+        for (recent_root, size) in &self.recent_roots {
+            ensure!(
+                merkle::root(leaves[..*size as usize].to_vec()) == *recent_root,
+                "merkle tree recent root {} doesn't match its leaves",
+                hex::encode(recent_root)
+            );
+        }
+
+        let mut batch = Batch::default();
+        let mut level = leaves;
+        for height in 0..DEPTH {
+            for (index, hash) in level.iter().enumerate() {
+                batch.put(Column::Merkle, self.node_key(height, index as u64), hash);
+            }
+            if level.len() % 2 == 1 {
+                level.push(self.empty_hashes[height]);
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| merkle::node_hash(&pair[0], &pair[1]))
+                .collect();
+        }
+        let root = level.first().copied().unwrap_or(self.empty_hashes[DEPTH]);
+        batch.put(Column::Merkle, self.node_key(DEPTH, 0), root);
+        Ok((root, batch))
+    }
+
+    fn index(&self, storage: &Storage, id: &[u8]) -> Result<Option<u64>> {
+        storage
+            .get(Column::Merkle, self.index_key(id))?
+            .map(|bytes| {
+                bytes
+                    .try_into()
+                    .map(u64::from_be_bytes)
+                    .map_err(|_| anyhow!("invalid merkle leaf index"))
+            })
+            .transpose()
+    }
+
+    fn node(
+        &self,
+        storage: &Storage,
+        updated: &HashMap<(usize, u64), Hash>,
+        level: usize,
+        index: u64,
+    ) -> Result<Hash> {
+        if let Some(hash) = updated.get(&(level, index)) {
+            return Ok(*hash);
+        }
+        match storage.get(Column::Merkle, self.node_key(level, index))? {
+            Some(bytes) => bytes.try_into().map_err(|_| anyhow!("invalid merkle node")),
+            None => Ok(self.empty_hashes[level]),
+        }
+    }
+
+    fn node_key(&self, level: usize, index: u64) -> Vec<u8> {
+        let mut key = vec![self.prefix, b'n', level as u8];
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    fn index_key(&self, id: &[u8]) -> Vec<u8> {
+        let mut key = vec![self.prefix, b'i'];
+        key.extend_from_slice(id);
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_prove() {
+        let storage = Storage::open(".db_test/merkle").unwrap();
+        let mut tree = MerkleTree::load(&storage, b'c').unwrap();
+        let empty_root = tree.root(&storage).unwrap();
+        assert_eq!(merkle::empty_hashes()[DEPTH], empty_root);
+
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..5u8)
+            .map(|i| (vec![i], format!("leaf{i}").into_bytes()))
+            .collect();
+        let mut batch = Batch::default();
+        let root = tree.append(&storage, &entries, &mut batch).unwrap();
+        storage.write(batch).unwrap();
+
+        assert_ne!(empty_root, root);
+        assert_eq!(root, tree.root(&storage).unwrap());
+        for (id, leaf) in &entries {
+            let proof = tree.proof(&storage, id).unwrap().unwrap();
+            assert!(proof.verify(&root, leaf));
+        }
+        assert!(tree.proof(&storage, b"unknown").unwrap().is_none());
+
+        // reloading the tree gives the same state
+        let mut tree = MerkleTree::load(&storage, b'c').unwrap();
+        assert_eq!(root, tree.root(&storage).unwrap());
+
+        // leaves added after a root are not contained in it
+        let mut batch = Batch::default();
+        let new_root = tree
+            .append(&storage, &[(b"new".to_vec(), b"leaf".to_vec())], &mut batch)
+            .unwrap();
+        storage.write(batch).unwrap();
+        assert!(tree.contains(&storage, &[0], &root).unwrap());
+        assert!(!tree.contains(&storage, b"new", &root).unwrap());
+        assert!(tree.contains(&storage, b"new", &new_root).unwrap());
+        assert!(!tree.contains(&storage, b"unknown", &new_root).unwrap());
+        assert!(tree.contains(&storage, b"new", &empty_root).is_err());
+
+        // the nodes can be rebuilt from the leaves
+        let leaves: HashMap<Vec<u8>, Vec<u8>> = entries
+            .into_iter()
+            .chain([(b"new".to_vec(), b"leaf".to_vec())])
+            .collect();
+        let (rebuilt_root, _) = tree
+            .rebuild(&storage, |id| Ok(Some(leaves[id].clone())))
+            .unwrap();
+        assert_eq!(new_root, rebuilt_root);
+        assert!(tree
+            .rebuild(&storage, |_| Ok(Some(b"fake".to_vec())))
+            .is_err());
+
+        // missing leaf data is replaced by the stored leaf hashes
+        let (rebuilt_root, _) = tree.rebuild(&storage, |_| Ok(None)).unwrap();
+        assert_eq!(new_root, rebuilt_root);
+    }
+
+    #[test]
+    fn stale_roots() {
+        let storage = Storage::open(".db_test/merkle_stale").unwrap();
+        let mut tree = MerkleTree::load(&storage, b'c').unwrap();
+        let mut roots = vec![];
+        for i in 0..=RECENT_ROOTS as u32 {
+            let mut batch = Batch::default();
+            let entry = (i.to_be_bytes().to_vec(), b"leaf".to_vec());
+            roots.push(tree.append(&storage, &[entry], &mut batch).unwrap());
+            storage.write(batch).unwrap();
+        }
+
+        // only the most recent roots are kept, older ones are rejected with an error that isn't retryable
+        assert!(tree
+            .contains(&storage, &0u32.to_be_bytes(), &roots[1])
+            .unwrap());
+        let error = tree
+            .contains(&storage, &0u32.to_be_bytes(), &roots[0])
+            .unwrap_err();
+        let error = AppError::find(&error);
+        assert!(matches!(error, AppError::StaleStateRoot(_)));
+        assert!(!error.is_retryable());
+    }
+}
@@ -0,0 +1,13 @@
+//! The ABCI application run by the `snarkvm_abci` binary, connecting the aleo specific logic (e.g. program proof
+//! verification) to Tendermint. It's part of the library so other targets, like the fuzz tests, can run it.
+
+pub mod application;
+mod block_verifier;
+mod mempool;
+mod merkle_tree;
+mod program_store;
+mod record_store;
+mod snapshots;
+mod storage;
+mod validator_set;
+mod verification_cache;
@@ -0,0 +1,324 @@
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::vm;
+use anyhow::{anyhow, ensure, Result};
+use log::{debug, error};
+use rocksdb::IteratorMode;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub type StoredProgram = (vm::Program, vm::VerifyingKeyMap);
+
+type Key = vm::ProgramID;
+type Value = StoredProgram;
+
+/// The program store tracks programs that have been deployed to the OS
+#[derive(Clone, Debug)]
+pub struct ProgramStore {
+    /// Channel used to send operations to the task that manages the store state.
+    command_sender: Sender<Command>,
+    /// Handle of the thread that manages the store state, joined when the store is shut down.
+    thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Add(Key, Box<Value>, SyncSender<Result<()>>),
+    Get(Key, SyncSender<Result<Option<Value>>>),
+    Exists(Key, SyncSender<Result<bool>>),
+    Commit(SyncSender<(Vec<u8>, Batch)>),
+    Restore(SyncSender<Result<Vec<u8>>>),
+    Shutdown,
+}
+
+impl ProgramStore {
+    /// Start a new program store on a new thread, keeping the deployed programs in the programs column of the given storage.
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        // programs added since the last commit, as (program id, serialized program and keys) pairs.
+        let mut added_programs = BTreeMap::new();
+        // digests of the committed programs by program id, so the hash of the whole store can be calculated on every
+        // block without reading the programs back from the storage.
+        let mut digests = load_digests(&storage)?;
+
+        let (command_sender, command_receiver): (Sender<Command>, Receiver<Command>) = channel();
+
+        let thread = thread::spawn(move || {
+            while let Ok(command) = command_receiver.recv() {
+                match command {
+                    Command::Shutdown => break,
+                    Command::Add(program_id, program_keys, reply_to) => {
+                        let program_id = program_id.to_string();
+                        let result = program_exists(&storage, &added_programs, &program_id)
+                            .and_then(|exists| {
+                                ensure!(
+                                    !exists,
+                                    "Program {} already exists in the store",
+                                    &program_id
+                                );
+                                let program_keys = bincode::serialize(&program_keys)?;
+                                added_programs.insert(program_id, program_keys);
+                                Ok(())
+                            });
+
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Get(program_id, reply_to) => {
+                        // programs deployed in the current block are already available to the following transactions
+                        let program_id = program_id.to_string();
+                        let result = match added_programs.get(&program_id) {
+                            Some(value) => Ok(Some(value.clone())),
+                            None => storage.get(Column::Programs, &program_id),
+                        }
+                        .and_then(|value| {
+                            value
+                                .map(|value| bincode::deserialize::<Value>(&value))
+                                .transpose()
+                                .map_err(anyhow::Error::from)
+                        });
+
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Exists(program_id, reply_to) => {
+                        let program_id = program_id.to_string();
+                        let result = program_exists(&storage, &added_programs, &program_id);
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Commit(reply_to) => {
+                        let mut batch = Batch::default();
+                        for (program_id, program_keys) in std::mem::take(&mut added_programs) {
+                            digests
+                                .insert(program_id.clone(), Sha256::digest(&program_keys).into());
+                            batch.put(Column::Programs, program_id, program_keys);
+                        }
+                        reply_to
+                            .send((hash_digests(&digests), batch))
+                            .unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Restore(reply_to) => {
+                        added_programs.clear();
+                        let result = load_digests(&storage).map(|loaded| {
+                            digests = loaded;
+                            hash_digests(&digests)
+                        });
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                };
+            }
+        });
+        let program_store = Self {
+            command_sender,
+            thread: Arc::new(Mutex::new(Some(thread))),
+        };
+
+        program_store.load_credits()?;
+        Ok(program_store)
+    }
+
+    /// Returns a program
+    pub fn get(&self, program_id: &vm::ProgramID) -> Result<Option<StoredProgram>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender
+            .send(Command::Get(*program_id, reply_sender))?;
+
+        reply_receiver.recv()?
+    }
+
+    /// Adds a program to the store
+    pub fn add(
+        &self,
+        program_id: &vm::ProgramID,
+        program: &vm::Program,
+        verifying_keys: &vm::VerifyingKeyMap,
+    ) -> Result<()> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender.send(Command::Add(
+            *program_id,
+            Box::new((program.clone(), verifying_keys.clone())),
+            reply_sender,
+        ))?;
+
+        reply_receiver.recv()?
+    }
+
+    /// Returns whether a program ID is already stored
+    pub fn exists(&self, program_id: &vm::ProgramID) -> Result<bool> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender
+            .send(Command::Exists(*program_id, reply_sender))?;
+
+        reply_receiver.recv()?
+    }
+
+    /// Returns a hash of all the deployed programs and their verifying keys, including the ones added since the last call,
+    /// to be included in the application state hash, and the batch that persists the added ones.
+    pub fn commit(&self) -> Result<(Vec<u8>, Batch)> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender.send(Command::Commit(reply_sender))?;
+        Ok(reply_receiver.recv()?)
+    }
+
+    /// Reload the store after the storage contents were replaced, e.g. by restoring a snapshot, dropping the programs
+    /// added since the last commit. Returns the hash of the stored programs, as calculated on commit.
+    pub fn restore(&self) -> Result<Vec<u8>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender.send(Command::Restore(reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Stop the thread that manages the store and wait for it to finish, releasing its handle to the storage.
+    /// Other clones of the store can't be used afterwards.
+    pub fn shutdown(&self) -> Result<()> {
+        // the thread may already be gone if another clone shut it down
+        self.command_sender
+            .send(Command::Shutdown)
+            .unwrap_or_default();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            thread
+                .join()
+                .map_err(|_| anyhow!("the store thread panicked"))?;
+        }
+        Ok(())
+    }
+
+    fn load_credits(&self) -> Result<()> {
+        let (credits_program, keys) = crate::load_credits();
+
+        if self.exists(credits_program.id())? {
+            debug!("Credits program already exists in program store");
+            Ok(())
+        } else {
+            debug!("Loading credits.aleo as part of Program Store initialization");
+            let key_map = keys
+                .into_iter()
+                .map(|(function, (_proving, verifying))| (function, verifying))
+                .collect();
+
+            self.add(credits_program.id(), &credits_program, &key_map)
+        }
+    }
+}
+
+/// Return whether the program with the given id was added since the last commit or is already in the storage.
+fn program_exists(
+    storage: &Storage,
+    added_programs: &BTreeMap<String, Vec<u8>>,
+    program_id: &str,
+) -> Result<bool> {
+    Ok(added_programs.contains_key(program_id)
+        || storage.get(Column::Programs, program_id)?.is_some())
+}
+
+/// Calculate the digests of the programs stored in the given storage, by program id.
+fn load_digests(storage: &Storage) -> Result<BTreeMap<String, [u8; 32]>> {
+    storage
+        .iterator(Column::Programs, IteratorMode::Start)?
+        .map(|entry| -> Result<(String, [u8; 32])> {
+            let (program_id, program_keys) = entry?;
+            let program_id = String::from_utf8(program_id.to_vec())?;
+            Ok((program_id, Sha256::digest(program_keys).into()))
+        })
+        .collect()
+}
+
+/// Hash the digests of the stored programs, sorted by program id so the result doesn't depend on the order of deployment.
+fn hash_digests(digests: &BTreeMap<String, [u8; 32]>) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update((digests.len() as u64).to_be_bytes());
+    for (program_id, digest) in digests {
+        hasher.update(program_id);
+        hasher.update(digest);
+    }
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{self, Program};
+    use std::{fs, str::FromStr};
+
+    #[ctor::ctor]
+    fn init() {
+        // todo: this fails with error because it's already initialised
+        /* simple_logger::SimpleLogger::new()
+        .env()
+        .with_level(log::LevelFilter::Info)
+        .init()
+        .unwrap();  */
+
+        fs::remove_dir_all(db_path("")).unwrap_or_default();
+    }
+
+    fn db_path(suffix: &str) -> String {
+        format!(".db_test/{suffix}")
+    }
+
+    #[test]
+    fn add_program() {
+        let storage = Arc::new(Storage::open(&db_path("program")).unwrap());
+        let store = ProgramStore::new(storage.clone()).unwrap();
+
+        let program_path = format!("{}{}", env!("CARGO_MANIFEST_DIR"), "/aleo/hello.aleo");
+        let program =
+            Program::from_str(fs::read_to_string(program_path).unwrap().as_str()).unwrap();
+
+        let get_program = store.get(program.id());
+
+        assert!(get_program.unwrap().is_none());
+
+        let storage_attempt = store_program(&store, "/aleo/hello.aleo");
+        let program = storage_attempt.unwrap();
+        assert!(store.exists(program.id()).unwrap());
+        assert!(store.get(program.id()).unwrap().is_some());
+
+        // the program is only persisted after the commit batch is written
+        let reloaded = ProgramStore::new(storage.clone()).unwrap();
+        assert!(!reloaded.exists(program.id()).unwrap());
+        let (hash, batch) = store.commit().unwrap();
+        storage.write(batch).unwrap();
+        assert!(reloaded.exists(program.id()).unwrap());
+
+        // the hash covers every stored program, so it can be recalculated from the storage
+        assert_eq!(hash, reloaded.restore().unwrap());
+        assert_eq!(hash, store.commit().unwrap().0);
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+        std::mem::forget(reloaded);
+    }
+
+    #[test]
+    fn credits_loaded() {
+        let program = Program::credits().expect("Problem loading Credits");
+
+        let storage = Arc::new(Storage::open(&db_path("credits")).unwrap());
+        let get_program = storage.get(Column::Programs, program.id().to_string());
+        assert!(get_program.unwrap().is_none());
+
+        let store = ProgramStore::new(storage).unwrap();
+
+        assert!(store.exists(program.id()).unwrap());
+    }
+
+    fn store_program(program_store: &ProgramStore, path: &str) -> Result<vm::Program> {
+        let program_path = format!("{}{}", env!("CARGO_MANIFEST_DIR"), path);
+
+        let program_string = fs::read_to_string(program_path).unwrap();
+        let program = vm::generate_program(&program_string)?;
+
+        // generate program keys (proving and verifying) and keep the verifying one for the store
+        let keys = vm::synthesize_program_keys(&program, &[])?
+            .into_iter()
+            .map(|(i, (_, verifying_key))| (i, verifying_key))
+            .collect();
+
+        program_store.add(program.id(), &program, &keys)?;
+
+        Ok(program)
+    }
+}
@@ -0,0 +1,1133 @@
+use crate::blockchain::merkle_tree::MerkleTree;
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::error::AppError;
+use crate::merkle::{Hash, MerkleProof};
+use crate::vm::{EncryptedRecord, Field};
+use anyhow::{anyhow, ensure, Result};
+use itertools::Itertools;
+use log::error;
+use rocksdb::{Direction, IteratorMode};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// because both serial numbers and Commitments are really fields, define types to differentiate them
+type SerialNumber = Field;
+type Commitment = Field;
+
+// TODO: Key and Value types should be concrete types instead of serialized data like in
+// program store, so that type errors bubble up asap (ie from the interaction with the DB)
+type Key = Vec<u8>;
+type Value = Vec<u8>;
+
+/// Internal channel reply for the scan command
+type ScanReply = (Vec<(Key, Value)>, Option<Key>);
+/// Public return type for the scan command, with the commitment to continue scanning from, if any.
+type ScanResult = (Vec<(Commitment, EncryptedRecord)>, Option<Commitment>);
+/// Internal channel reply for the scan since command
+type ScanSinceReply = (Vec<(Key, Value)>, Option<(u64, Key)>);
+/// Public return type for the scan since command, with the height and commitment to continue scanning from, if any.
+type ScanSinceResult = (
+    Vec<(Commitment, EncryptedRecord)>,
+    Option<(u64, Commitment)>,
+);
+/// Roots of the commitment and serial number trees.
+type Roots = (Hash, Hash);
+
+/// Key of the meta column with the height before which the record ciphertexts were pruned.
+const PRUNED_BEFORE_KEY: &[u8] = b"records_pruned_before";
+
+/// The record store tracks the known unspent and spent record sets (similar to bitcoin's UTXO set)
+/// according to the transactions that are committed to the ledger.
+/// Because of how Tendermint ABCI applications are structured, this store is prepared to buffer
+/// updates (new unspent record additions and spending of known records) while transactions are being
+/// processed, and return them together as a storage batch when the block is committed.
+/// Committed record commitments and serial numbers are also appended to merkle trees, so the store can
+/// produce proofs that a record was added to the ledger or spent.
+/// The ciphertexts of old records can be pruned to save space. Their commitments are kept, along with the merkle
+/// trees and spent serial numbers, so transactions spending them can still be validated.
+#[derive(Clone, Debug)]
+pub struct RecordStore {
+    /// Channel used to send operations to the task that manages the store state.
+    command_sender: Sender<Command>,
+    /// Handle of the thread that manages the store state, joined when the store is shut down.
+    thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Add(Key, Value, SyncSender<Result<()>>),
+    Spend(Key, SyncSender<Result<()>>),
+    IsUnspent(Key, SyncSender<Result<bool>>),
+    AreSpent(Vec<Key>, SyncSender<Result<Vec<bool>>>),
+    Commit(u64, SyncSender<Result<(Roots, Batch)>>),
+    Prune(u64, SyncSender<Result<Batch>>),
+    Roots(SyncSender<Result<Roots>>),
+    Restore(u64, SyncSender<Result<Roots>>),
+    Get(Key, SyncSender<Result<Option<Value>>>),
+    Exists {
+        commitment: Key,
+        root: Hash,
+        reply_sender: SyncSender<Result<bool>>,
+    },
+    Prove {
+        tree: Tree,
+        keys: Vec<Key>,
+        reply_sender: SyncSender<Result<Vec<Option<MerkleProof>>>>,
+    },
+    ScanSpentRecords(SyncSender<Result<HashSet<SerialNumber>>>),
+    ScanRecords {
+        from: Option<Key>,
+        limit: Option<usize>,
+        reply_sender: SyncSender<Result<ScanReply>>,
+    },
+    ScanRecordsSince {
+        height: u64,
+        from: Option<Key>,
+        limit: Option<usize>,
+        reply_sender: SyncSender<Result<ScanSinceReply>>,
+    },
+    Shutdown,
+}
+
+#[derive(Debug)]
+enum Tree {
+    Commitments,
+    SerialNumbers,
+}
+
+impl RecordStore {
+    /// Start a new record store on a new thread.
+    /// The store uses the following columns of the given storage:
+    /// - records: the record ciphertexts by commitment.
+    /// - spent serial numbers: tracked to ensure that records aren't spent more than once
+    ///   (without having to _know_ the actual record contents).
+    /// - record heights: the commitments of the records added at each block height, with keys prefixed by the height.
+    ///   Used to scan the records added since a given height.
+    /// - merkle: the nodes of the commitment and serial number merkle trees.
+    /// - meta: the height before which record ciphertexts were pruned, if any.
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        let mut commitment_tree = MerkleTree::load(&storage, b'c')?;
+        let mut serial_number_tree = MerkleTree::load(&storage, b's')?;
+        let mut pruned_before = read_pruned_before(&storage)?;
+
+        // map to store temporary unspent record additions until a block is comitted.
+        let mut record_buffer = HashMap::new();
+
+        // map to store temporary spent record additions until a block is comitted.
+        let mut spent_buffer = HashMap::new();
+
+        let (command_sender, command_receiver): (Sender<Command>, Receiver<Command>) = channel();
+
+        let thread = thread::spawn(move || {
+            while let Ok(command) = command_receiver.recv() {
+                match command {
+                    Command::Shutdown => break,
+                    Command::Add(commitment, ciphertext, reply_to) => {
+                        let result = if record_buffer.contains_key(&commitment) {
+                            Err(anyhow!(
+                                "record {} already exists",
+                                String::from_utf8_lossy(&commitment)
+                            ))
+                        } else {
+                            key_exists(&storage, Column::Records, &commitment).and_then(|exists| {
+                                ensure!(
+                                    !exists,
+                                    "record {} already exists",
+                                    String::from_utf8_lossy(&commitment)
+                                );
+                                record_buffer.insert(commitment, ciphertext);
+                                Ok(())
+                            })
+                        };
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Spend(serial_number, reply_to) => {
+                        // the existence of the spent record is checked separately, since the serial number can't
+                        // be related to its commitment without the data revealed in the transaction
+                        let result = if spent_buffer.contains_key(&serial_number) {
+                            Err(anyhow!("record already spent"))
+                        } else {
+                            key_exists(&storage, Column::SpentSerialNumbers, &serial_number)
+                                .and_then(|spent| {
+                                    ensure!(!spent, "record already spent");
+                                    spent_buffer.insert(serial_number, "1".as_bytes());
+                                    Ok(())
+                                })
+                        };
+
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::IsUnspent(serial_number, reply_to) => {
+                        let is_unspent =
+                            key_exists(&storage, Column::SpentSerialNumbers, &serial_number)
+                                .map(|spent| !spent && !spent_buffer.contains_key(&serial_number));
+                        reply_to
+                            .send(is_unspent)
+                            .unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::AreSpent(serial_numbers, reply_to) => {
+                        let are_spent = serial_numbers
+                            .iter()
+                            .map(|serial_number| {
+                                key_exists(&storage, Column::SpentSerialNumbers, serial_number)
+                            })
+                            .collect();
+                        reply_to.send(are_spent).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Commit(height, reply_to) => {
+                        // append the block changes sorted to the trees, so every node that applied the same block
+                        // gets the same roots regardless of the order in which the records were added or spent.
+                        let new_records: Vec<(Key, Value)> = record_buffer
+                            .iter()
+                            .sorted()
+                            .map(|(commitment, ciphertext)| {
+                                (
+                                    commitment.clone(),
+                                    [commitment.as_slice(), ciphertext.as_slice()].concat(),
+                                )
+                            })
+                            .collect();
+                        let new_serial_numbers: Vec<(Key, Value)> = spent_buffer
+                            .keys()
+                            .sorted()
+                            .map(|serial_number| (serial_number.clone(), serial_number.clone()))
+                            .collect();
+
+                        let mut batch = Batch::default();
+                        let roots = commitment_tree
+                            .append(&storage, &new_records, &mut batch)
+                            .and_then(|commitments_root| {
+                                let serial_numbers_root = serial_number_tree.append(
+                                    &storage,
+                                    &new_serial_numbers,
+                                    &mut batch,
+                                )?;
+                                Ok((commitments_root, serial_numbers_root))
+                            });
+
+                        // add new records to store, indexed by height
+                        for (key, value) in record_buffer.drain() {
+                            batch.put(
+                                Column::RecordHeights,
+                                [height.to_be_bytes().as_slice(), key.as_slice()].concat(),
+                                b"",
+                            );
+                            batch.put(Column::Records, key, value);
+                        }
+
+                        // add all buffer spent to db spent, i.e. persisted consumed records (as a serial number for security)
+                        for (key, value) in spent_buffer.drain() {
+                            batch.put(Column::SpentSerialNumbers, key, value);
+                        }
+
+                        reply_to
+                            .send(roots.map(|roots| (roots, batch)))
+                            .unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Prune(before, reply_to) => {
+                        let result = if before > pruned_before {
+                            prune_records(&storage, before).map(|batch| {
+                                pruned_before = before;
+                                batch
+                            })
+                        } else {
+                            Ok(Batch::default())
+                        };
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Restore(height, reply_to) => {
+                        // changes that weren't committed don't apply to the restored state
+                        record_buffer.clear();
+                        spent_buffer.clear();
+                        let result =
+                            restore_trees(&storage).and_then(|(commitments, serial_numbers)| {
+                                commitment_tree = commitments;
+                                serial_number_tree = serial_numbers;
+                                pruned_before = restore_pruned_before(&storage, height)?;
+                                Ok(())
+                            });
+                        let roots = result.and_then(|_| {
+                            Ok((
+                                commitment_tree.root(&storage)?,
+                                serial_number_tree.root(&storage)?,
+                            ))
+                        });
+                        reply_to.send(roots).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Roots(reply_to) => {
+                        let roots = commitment_tree.root(&storage).and_then(|commitments_root| {
+                            Ok((commitments_root, serial_number_tree.root(&storage)?))
+                        });
+                        reply_to.send(roots).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Get(commitment, reply_to) => {
+                        let result = storage
+                            .get(Column::Records, &commitment)
+                            .and_then(|record| match record {
+                                Some(ciphertext) if ciphertext.is_empty() => {
+                                    Err(AppError::PrunedRecords(format!(
+                                        "record {} was pruned",
+                                        String::from_utf8_lossy(&commitment)
+                                    ))
+                                    .into())
+                                }
+                                record => Ok(record),
+                            });
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Exists {
+                        commitment,
+                        root,
+                        reply_sender: reply_to,
+                    } => {
+                        let result = commitment_tree.contains(&storage, &commitment, &root);
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::Prove {
+                        tree,
+                        keys,
+                        reply_sender: reply_to,
+                    } => {
+                        let tree = match tree {
+                            Tree::Commitments => &commitment_tree,
+                            Tree::SerialNumbers => &serial_number_tree,
+                        };
+                        let result = keys.iter().map(|key| tree.proof(&storage, key)).collect();
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::ScanRecords {
+                        from,
+                        limit,
+                        reply_sender: reply_to,
+                    } => {
+                        let iterator_mode = from.as_ref().map_or(IteratorMode::Start, |key| {
+                            IteratorMode::From(key, Direction::Forward)
+                        });
+                        let result =
+                            storage
+                                .iterator(Column::Records, iterator_mode)
+                                .and_then(|iterator| {
+                                    let mut records = vec![];
+                                    let mut next_key = None;
+                                    for entry in iterator {
+                                        let (key, record) = entry?;
+                                        // pruned records are skipped
+                                        if record.is_empty() {
+                                            continue;
+                                        }
+                                        if limit.map_or(false, |l| records.len() >= l) {
+                                            next_key = Some(key.to_vec());
+                                            break;
+                                        }
+                                        records.push((key.to_vec(), record.to_vec()));
+                                    }
+                                    Ok((records, next_key))
+                                });
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::ScanRecordsSince {
+                        height,
+                        from,
+                        limit,
+                        reply_sender: reply_to,
+                    } => {
+                        let result = if height < pruned_before {
+                            Err(AppError::PrunedRecords(format!(
+                                "records before height {pruned_before} were pruned"
+                            ))
+                            .into())
+                        } else {
+                            scan_records_since(&storage, height, from, limit)
+                        };
+                        reply_to.send(result).unwrap_or_else(|e| error!("{}", e));
+                    }
+                    Command::ScanSpentRecords(reply_sender) => {
+                        let result = storage
+                            .iterator(Column::SpentSerialNumbers, IteratorMode::Start)
+                            .and_then(|iterator| {
+                                iterator
+                                    .map(|entry| -> Result<SerialNumber> {
+                                        let (k, _) = entry?;
+                                        SerialNumber::from_str(&String::from_utf8_lossy(&k))
+                                    })
+                                    .collect()
+                            });
+                        reply_sender
+                            .send(result)
+                            .unwrap_or_else(|e| error!("{}", e));
+                    }
+                };
+            }
+        });
+        Ok(Self {
+            command_sender,
+            thread: Arc::new(Mutex::new(Some(thread))),
+        })
+    }
+
+    /// Saves a new unspent record to the write buffer
+    pub fn add(&self, commitment: Commitment, record: EncryptedRecord) -> Result<()> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let commitment = commitment.to_string().into_bytes();
+        let ciphertext = record.to_string().into_bytes();
+
+        self.command_sender
+            .send(Command::Add(commitment, ciphertext, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Marks a record as spent in the write buffer.
+    /// Fails if the record is not found or was already spent.
+    pub fn spend(&self, serial_number: &SerialNumber) -> Result<()> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let serial_number = serial_number.to_string().into_bytes();
+        self.command_sender
+            .send(Command::Spend(serial_number, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Empty the write buffer and return its changes as a batch, to be written to the storage along with the rest of the block.
+    /// The new records are indexed by the given height.
+    /// Also returns the updated roots of the commitment and serial number trees, to be included in the application state hash.
+    pub fn commit(&self, height: u64) -> Result<(Roots, Batch)> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender
+            .send(Command::Commit(height, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Drop the ciphertexts of the records added before the given height, returning the changes as a batch to be written
+    /// along with the rest of the block. Pruned records can't be fetched or scanned anymore.
+    pub fn prune(&self, before_height: u64) -> Result<Batch> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender
+            .send(Command::Prune(before_height, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Return the current roots of the commitment and serial number trees.
+    pub fn roots(&self) -> Result<Roots> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender.send(Command::Roots(reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Return the ciphertext of the committed record with the given commitment, if any.
+    /// Fails if the record was pruned.
+    pub fn get(&self, commitment: &Commitment) -> Result<Option<EncryptedRecord>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let commitment = commitment.to_string().into_bytes();
+        self.command_sender
+            .send(Command::Get(commitment, reply_sender))?;
+        reply_receiver
+            .recv()??
+            .map(|record| EncryptedRecord::from_str(&String::from_utf8_lossy(&record)))
+            .transpose()
+    }
+
+    /// Returns whether the record with the given commitment was already committed to the ledger when the
+    /// commitment tree had the given root. Fails if the root is not among the most recent ones.
+    pub fn exists(&self, commitment: &Commitment, root: &Hash) -> Result<bool> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let commitment = commitment.to_string().into_bytes();
+        self.command_sender.send(Command::Exists {
+            commitment,
+            root: *root,
+            reply_sender,
+        })?;
+        reply_receiver.recv()?
+    }
+
+    /// Reload the store after its contents were restored from a snapshot of the given height. The merkle tree nodes are
+    /// recalculated from the stored records and serial numbers, and the resulting roots are returned so they can be
+    /// checked against trusted ones. The height index of the records isn't part of the snapshots, since it can't be
+    /// checked against the roots, so the records up to the snapshot height are taken as pruned: they can still be
+    /// fetched by commitment, but not scanned by height.
+    pub fn restore(&self, height: u64) -> Result<Roots> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+        self.command_sender
+            .send(Command::Restore(height, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Return the merkle proofs of the given commitments in the tree of committed records.
+    /// Unknown commitments get a None proof.
+    pub fn prove_records(&self, commitments: &[Commitment]) -> Result<Vec<Option<MerkleProof>>> {
+        self.prove(Tree::Commitments, commitments)
+    }
+
+    /// Return the merkle proofs of the given serial numbers in the tree of committed spent serial numbers.
+    /// Unknown serial numbers get a None proof.
+    pub fn prove_serial_numbers(
+        &self,
+        serial_numbers: &[SerialNumber],
+    ) -> Result<Vec<Option<MerkleProof>>> {
+        self.prove(Tree::SerialNumbers, serial_numbers)
+    }
+
+    fn prove(&self, tree: Tree, keys: &[Field]) -> Result<Vec<Option<MerkleProof>>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let keys = keys
+            .iter()
+            .map(|key| key.to_string().into_bytes())
+            .collect();
+        self.command_sender.send(Command::Prove {
+            tree,
+            keys,
+            reply_sender,
+        })?;
+        reply_receiver.recv()?
+    }
+
+    /// Returns whether a record by the given serial_number is known and not spent
+    pub fn is_unspent(&self, serial_number: &SerialNumber) -> Result<bool> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let serial_number = serial_number.to_string().into_bytes();
+        self.command_sender
+            .send(Command::IsUnspent(serial_number, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Returns whether each of the given serial numbers was spent in a committed block.
+    /// Unlike `is_unspent`, this ignores the changes buffered for the block being processed.
+    pub fn are_spent(&self, serial_numbers: &[SerialNumber]) -> Result<Vec<bool>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        let serial_numbers = serial_numbers
+            .iter()
+            .map(|serial_number| serial_number.to_string().into_bytes())
+            .collect();
+        self.command_sender
+            .send(Command::AreSpent(serial_numbers, reply_sender))?;
+        reply_receiver.recv()?
+    }
+
+    /// Return up to `limit` record ciphertexts, starting from the given commitment.
+    pub fn scan(&self, from: Option<Commitment>, limit: Option<usize>) -> Result<ScanResult> {
+        let from = from.map(|commitment| commitment.to_string().into_bytes());
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender.send(Command::ScanRecords {
+            from,
+            limit,
+            reply_sender,
+        })?;
+
+        let (results, next_key) = reply_receiver.recv()??;
+        let next_key = next_key
+            .map(|commitment| Commitment::from_str(&String::from_utf8_lossy(&commitment)))
+            .transpose()?;
+        Ok((parse_records(results)?, next_key))
+    }
+
+    /// Return up to `limit` record ciphertexts added to the ledger since the given block height, starting from the
+    /// given commitment within that block, if any. Along with them, returns the height and commitment to continue
+    /// scanning from when the limit was reached. Fails if the records at that height were pruned.
+    pub fn scan_since(
+        &self,
+        height: u64,
+        from: Option<Commitment>,
+        limit: Option<usize>,
+    ) -> Result<ScanSinceResult> {
+        let from = from.map(|commitment| commitment.to_string().into_bytes());
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender.send(Command::ScanRecordsSince {
+            height,
+            from,
+            limit,
+            reply_sender,
+        })?;
+
+        let (results, next) = reply_receiver.recv()??;
+        let next = next
+            .map(|(height, commitment)| -> Result<(u64, Commitment)> {
+                Ok((
+                    height,
+                    Commitment::from_str(&String::from_utf8_lossy(&commitment))?,
+                ))
+            })
+            .transpose()?;
+        Ok((parse_records(results)?, next))
+    }
+
+    // TODO: implement way of limiting response size/count or optimization for better scaling
+    /// Return all serial numbers
+    pub fn scan_spent(&self) -> Result<HashSet<SerialNumber>> {
+        let (reply_sender, reply_receiver) = sync_channel(0);
+
+        self.command_sender
+            .send(Command::ScanSpentRecords(reply_sender))?;
+
+        reply_receiver.recv()?
+    }
+
+    /// Stop the thread that manages the store and wait for it to finish, releasing its handle to the storage.
+    /// Other clones of the store can't be used afterwards.
+    pub fn shutdown(&self) -> Result<()> {
+        // the thread may already be gone if another clone shut it down
+        self.command_sender
+            .send(Command::Shutdown)
+            .unwrap_or_default();
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            thread
+                .join()
+                .map_err(|_| anyhow!("the store thread panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_records(records: Vec<(Key, Value)>) -> Result<Vec<(Commitment, EncryptedRecord)>> {
+    records
+        .iter()
+        .map(|(commitment, record)| {
+            let commitment = Commitment::from_str(&String::from_utf8_lossy(commitment))?;
+            let record = EncryptedRecord::from_str(&String::from_utf8_lossy(record))?;
+            Ok((commitment, record))
+        })
+        .collect()
+}
+
+/// Rebuild the nodes of the commitment and serial number trees from their leaves and load them again.
+/// Also checks that the records and spent serial numbers are exactly the leaves of the trees.
+/// The leaves of pruned records are taken from the stored tree, since their ciphertexts aren't available.
+fn restore_trees(storage: &Storage) -> Result<(MerkleTree, MerkleTree)> {
+    let commitment_tree = MerkleTree::load(storage, b'c')?;
+    let (_, commitments_batch) = commitment_tree.rebuild(storage, |commitment| {
+        let ciphertext = storage
+            .get(Column::Records, commitment)?
+            .ok_or_else(|| anyhow!("missing record for commitment tree leaf"))?;
+        if ciphertext.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some([commitment, ciphertext.as_slice()].concat()))
+        }
+    })?;
+    let serial_number_tree = MerkleTree::load(storage, b's')?;
+    let (_, serial_numbers_batch) = serial_number_tree.rebuild(storage, |serial_number| {
+        ensure!(
+            storage
+                .get(Column::SpentSerialNumbers, serial_number)?
+                .is_some(),
+            "missing spent serial number for serial number tree leaf"
+        );
+        Ok(Some(serial_number.to_vec()))
+    })?;
+
+    let records = storage
+        .iterator(Column::Records, IteratorMode::Start)?
+        .try_fold(0usize, |count, entry| entry.map(|_| count + 1))?;
+    let spent = storage
+        .iterator(Column::SpentSerialNumbers, IteratorMode::Start)?
+        .try_fold(0usize, |count, entry| entry.map(|_| count + 1))?;
+    ensure!(
+        records as u64 == commitment_tree.size() && spent as u64 == serial_number_tree.size(),
+        "stored records and serial numbers don't match the merkle trees"
+    );
+
+    let mut batch = commitments_batch;
+    batch.append(serial_numbers_batch);
+    storage.write(batch)?;
+    Ok((commitment_tree, serial_number_tree))
+}
+
+/// Return up to `limit` committed records added since the given height, starting from the given commitment within
+/// that block, along with the (height, commitment) position of the first record left out, if any. Records are indexed
+/// by height and commitment, so a page can end in the middle of a block and the caller continue from that position.
+fn scan_records_since(
+    storage: &Storage,
+    height: u64,
+    from: Option<Key>,
+    limit: Option<usize>,
+) -> Result<ScanSinceReply> {
+    let mut start = height.to_be_bytes().to_vec();
+    start.extend(from.unwrap_or_default());
+    let mut commitments = vec![];
+    let mut next = None;
+    for entry in storage.iterator(
+        Column::RecordHeights,
+        IteratorMode::From(&start, Direction::Forward),
+    )? {
+        let (key, _) = entry?;
+        let (key_height, commitment) = key.split_at(8);
+        if limit.map_or(false, |l| commitments.len() >= l) {
+            next = Some((
+                u64::from_be_bytes(key_height.try_into()?),
+                commitment.to_vec(),
+            ));
+            break;
+        }
+        commitments.push(commitment.to_vec());
+    }
+
+    let records = commitments
+        .into_iter()
+        .map(|commitment| -> Result<(Key, Value)> {
+            let record = storage
+                .get(Column::Records, &commitment)?
+                .ok_or_else(|| anyhow!("missing indexed record"))?;
+            Ok((commitment, record))
+        })
+        .collect::<Result<_>>()?;
+    Ok((records, next))
+}
+
+/// Replace the ciphertexts of the records added before the given height with empty values, and remove them from the
+/// height index. The commitments are kept so the records can't be added again.
+fn prune_records(storage: &Storage, before: u64) -> Result<Batch> {
+    let mut batch = Batch::default();
+    for entry in storage.iterator(Column::RecordHeights, IteratorMode::Start)? {
+        let (key, _) = entry?;
+        let (key_height, commitment) = key.split_at(8);
+        if u64::from_be_bytes(key_height.try_into()?) >= before {
+            break;
+        }
+        batch.put(Column::Records, commitment, b"");
+        batch.delete(Column::RecordHeights, &key);
+    }
+    batch.put(Column::Meta, PRUNED_BEFORE_KEY, before.to_be_bytes());
+    Ok(batch)
+}
+
+/// Return the height before which the record ciphertexts were pruned, or zero if they weren't.
+fn read_pruned_before(storage: &Storage) -> Result<u64> {
+    storage
+        .get(Column::Meta, PRUNED_BEFORE_KEY)?
+        .map_or(Ok(0), |bytes| -> Result<u64> {
+            Ok(u64::from_be_bytes(bytes.as_slice().try_into()?))
+        })
+}
+
+/// Replace the pruned height of a storage restored from a snapshot of the given height, which came from an untrusted
+/// peer, with the height after the snapshot, since the records up to it aren't indexed by height. Returns the new value.
+fn restore_pruned_before(storage: &Storage, height: u64) -> Result<u64> {
+    let mut batch = Batch::default();
+    let pruned_before = if height > 0 {
+        batch.put(Column::Meta, PRUNED_BEFORE_KEY, (height + 1).to_be_bytes());
+        height + 1
+    } else {
+        batch.delete(Column::Meta, PRUNED_BEFORE_KEY);
+        0
+    };
+    storage.write(batch)?;
+    Ok(pruned_before)
+}
+
+/// Return whether the key is in the given column.
+fn key_exists(storage: &Storage, column: Column, key: &Key) -> Result<bool> {
+    Ok(storage.get(column, key)?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merkle;
+    use crate::vm::{self, PrivateKey};
+
+    use snarkvm::prelude::{Identifier, Network, ProgramID, Testnet3, Uniform};
+
+    use super::*;
+    use std::{fs, str::FromStr};
+    type PublicRecord = crate::vm::Record;
+
+    #[ctor::ctor]
+    fn init() {
+        simple_logger::SimpleLogger::new()
+            .env()
+            .with_level(log::LevelFilter::Info)
+            .init()
+            .unwrap();
+
+        fs::remove_dir_all(db_path("")).unwrap_or_default();
+    }
+
+    fn db_path(suffix: &str) -> String {
+        format!(".db_test/{suffix}")
+    }
+
+    fn new_store(name: &str) -> (RecordStore, Arc<Storage>) {
+        let storage = Arc::new(Storage::open(&db_path(name)).unwrap());
+        (RecordStore::new(storage.clone()).unwrap(), storage)
+    }
+
+    /// Commit the store changes and write them to the storage, returning the new roots.
+    fn commit(store: &RecordStore, storage: &Storage, height: u64) -> Roots {
+        let (roots, batch) = store.commit(height).unwrap();
+        storage.write(batch).unwrap();
+        roots
+    }
+
+    #[test]
+    fn add_and_spend_record() {
+        let (store, storage) = new_store("records1");
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        assert!(store.is_unspent(&serial_number).unwrap());
+        commit(&store, &storage, 1);
+        assert!(store.is_unspent(&serial_number).unwrap());
+        store.spend(&serial_number).unwrap();
+        assert!(!store.is_unspent(&serial_number).unwrap());
+        assert_eq!(vec![false], store.are_spent(&[serial_number]).unwrap());
+        commit(&store, &storage, 2);
+        assert!(!store.is_unspent(&serial_number).unwrap());
+        assert_eq!(vec![true], store.are_spent(&[serial_number]).unwrap());
+
+        let msg = store
+            .spend(&serial_number)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!("record already spent", msg);
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn no_double_add_record() {
+        let (store, storage) = new_store("records2");
+
+        let (record, commitment, _) = new_record();
+        store.add(commitment, record.clone()).unwrap();
+        let msg = store
+            .add(commitment, record)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!(format!("record {commitment} already exists"), msg);
+        commit(&store, &storage, 1);
+
+        let (record, commitment, _) = new_record();
+        store.add(commitment, record.clone()).unwrap();
+        commit(&store, &storage, 2);
+        let msg = store
+            .add(commitment, record)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!(format!("record {commitment} already exists"), msg);
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn spend_before_commit() {
+        let (store, storage) = new_store("records3");
+
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        assert!(store.is_unspent(&serial_number).unwrap());
+        store.spend(&serial_number).unwrap();
+        assert!(!store.is_unspent(&serial_number).unwrap());
+        commit(&store, &storage, 1);
+        assert!(!store.is_unspent(&serial_number).unwrap());
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn no_double_spend_record() {
+        let (store, storage) = new_store("records4");
+
+        // add, commit, spend, commit, fail spend
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        commit(&store, &storage, 1);
+        assert!(store.is_unspent(&serial_number).unwrap());
+        store.spend(&serial_number).unwrap();
+        commit(&store, &storage, 2);
+        assert!(!store.is_unspent(&serial_number).unwrap());
+        let msg = store
+            .spend(&serial_number)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!("record already spent", msg);
+
+        // add, commit, spend, fail spend, commit, fail spend
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        commit(&store, &storage, 3);
+        assert!(store.is_unspent(&serial_number).unwrap());
+        store.spend(&serial_number).unwrap();
+        let msg = store
+            .spend(&serial_number)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!("record already spent", msg);
+        commit(&store, &storage, 4);
+        assert!(!store.is_unspent(&serial_number).unwrap());
+        let msg = store
+            .spend(&serial_number)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!("record already spent", msg);
+
+        // add, spend, fail spend, commit
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        store.spend(&serial_number).unwrap();
+        let msg = store
+            .spend(&serial_number)
+            .unwrap_err()
+            .root_cause()
+            .to_string();
+        assert_eq!("record already spent", msg);
+        commit(&store, &storage, 5);
+        assert!(!store.is_unspent(&serial_number).unwrap());
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn commit_roots_are_deterministic() {
+        let (store1, storage1) = new_store("records5");
+        let (store2, storage2) = new_store("records6");
+
+        // apply the same changes in different order on each store
+        let (record1, commitment1, serial_number1) = new_record();
+        let (record2, commitment2, _) = new_record();
+        store1.add(commitment1, record1.clone()).unwrap();
+        store1.add(commitment2, record2.clone()).unwrap();
+        store2.add(commitment2, record2).unwrap();
+        store2.add(commitment1, record1).unwrap();
+        assert_eq!(commit(&store1, &storage1, 1), commit(&store2, &storage2, 1));
+
+        // spending a record changes the serial number root
+        store1.spend(&serial_number1).unwrap();
+        let (commitments1, serial_numbers1) = commit(&store1, &storage1, 2);
+        let (commitments2, serial_numbers2) = commit(&store2, &storage2, 2);
+        assert_eq!(commitments1, commitments2);
+        assert_ne!(serial_numbers1, serial_numbers2);
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store1);
+        std::mem::forget(store2);
+    }
+
+    #[test]
+    fn prove_committed_records() {
+        let (store, storage) = new_store("records7");
+
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record.clone()).unwrap();
+
+        // records can't be proven until committed
+        assert_eq!(vec![None], store.prove_records(&[commitment]).unwrap());
+        assert!(store.get(&commitment).unwrap().is_none());
+
+        let (commitments_root, _) = commit(&store, &storage, 1);
+        assert!(store.exists(&commitment, &commitments_root).unwrap());
+        let (_, unknown_commitment, _) = new_record();
+        assert!(!store
+            .exists(&unknown_commitment, &commitments_root)
+            .unwrap());
+        assert_eq!(
+            record.to_string(),
+            store.get(&commitment).unwrap().unwrap().to_string()
+        );
+        let proof = store.prove_records(&[commitment]).unwrap()[0]
+            .clone()
+            .unwrap();
+        assert!(proof.verify(
+            &commitments_root,
+            &merkle::record_leaf(&commitment, &record)
+        ));
+
+        store.spend(&serial_number).unwrap();
+        assert_eq!(
+            vec![None],
+            store.prove_serial_numbers(&[serial_number]).unwrap()
+        );
+        let (_, serial_numbers_root) = commit(&store, &storage, 2);
+        let proof = store.prove_serial_numbers(&[serial_number]).unwrap()[0]
+            .clone()
+            .unwrap();
+        assert!(proof.verify(&serial_numbers_root, serial_number.to_string().as_bytes()));
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn scan_records() {
+        let (store, storage) = new_store("records8");
+
+        // add two records at height 1 and one at height 3
+        let (record1, commitment1, _) = new_record();
+        let (record2, commitment2, _) = new_record();
+        let (record3, commitment3, _) = new_record();
+        store.add(commitment1, record1).unwrap();
+        store.add(commitment2, record2).unwrap();
+        commit(&store, &storage, 1);
+        commit(&store, &storage, 2);
+        store.add(commitment3, record3).unwrap();
+        commit(&store, &storage, 3);
+
+        // page through all records
+        let (page, next) = store.scan(None, Some(2)).unwrap();
+        assert_eq!(2, page.len());
+        let (page, next) = store.scan(next, Some(2)).unwrap();
+        assert_eq!(1, page.len());
+        assert!(next.is_none());
+
+        // pages can end in the middle of a block and continue from there
+        let mut scanned = vec![];
+        let (records, next) = store.scan_since(0, None, Some(1)).unwrap();
+        assert_eq!(1, records.len());
+        scanned.extend(records.iter().map(|(c, _)| *c));
+        let (next_height, next_commitment) = next.unwrap();
+        assert_eq!(1, next_height);
+        let (records, next) = store
+            .scan_since(next_height, Some(next_commitment), Some(1))
+            .unwrap();
+        assert_eq!(1, records.len());
+        scanned.extend(records.iter().map(|(c, _)| *c));
+        let (next_height, next_commitment) = next.unwrap();
+        assert_eq!(3, next_height);
+        let (records, next) = store
+            .scan_since(next_height, Some(next_commitment), Some(1))
+            .unwrap();
+        assert_eq!(
+            vec![commitment3],
+            records.iter().map(|(c, _)| *c).collect::<Vec<_>>()
+        );
+        assert!(next.is_none());
+        scanned.extend(records.iter().map(|(c, _)| *c));
+        scanned.sort_by_key(|c| c.to_string());
+        let mut expected = vec![commitment1, commitment2, commitment3];
+        expected.sort_by_key(|c| c.to_string());
+        assert_eq!(expected, scanned);
+
+        let (records, _) = store.scan_since(2, None, None).unwrap();
+        assert_eq!(1, records.len());
+        let (records, _) = store.scan_since(4, None, None).unwrap();
+        assert!(records.is_empty());
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    #[test]
+    fn changes_persisted_on_batch_write() {
+        let (store, storage) = new_store("records9");
+
+        let (record, commitment, serial_number) = new_record();
+        store.add(commitment, record).unwrap();
+        let (roots, batch) = store.commit(1).unwrap();
+
+        // until the batch is written, the changes are lost on restart
+        let reloaded = RecordStore::new(storage.clone()).unwrap();
+        assert!(reloaded.get(&commitment).unwrap().is_none());
+        assert_ne!(roots, reloaded.roots().unwrap());
+
+        storage.write(batch).unwrap();
+        let reloaded = RecordStore::new(storage).unwrap();
+        assert!(reloaded.get(&commitment).unwrap().is_some());
+        assert!(reloaded.is_unspent(&serial_number).unwrap());
+        assert_eq!(roots, reloaded.roots().unwrap());
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+        std::mem::forget(reloaded);
+    }
+
+    #[test]
+    fn shutdown_releases_storage() {
+        let (store, storage) = new_store("records11");
+        let clone = store.clone();
+        store.shutdown().unwrap();
+        assert_eq!(1, Arc::strong_count(&storage));
+
+        // the thread is gone for every clone of the store
+        assert!(clone.roots().is_err());
+        clone.shutdown().unwrap();
+    }
+
+    #[test]
+    fn prune_old_records() {
+        let (store, storage) = new_store("records10");
+
+        let (record1, commitment1, serial_number1) = new_record();
+        let (record2, commitment2, _) = new_record();
+        store.add(commitment1, record1.clone()).unwrap();
+        let (commitments_root, _) = commit(&store, &storage, 1);
+        store.add(commitment2, record2).unwrap();
+        commit(&store, &storage, 2);
+
+        let batch = store.prune(2).unwrap();
+        storage.write(batch).unwrap();
+
+        // the pruned ciphertexts can't be fetched anymore
+        assert!(store.get(&commitment1).is_err());
+        assert!(store.get(&commitment2).unwrap().is_some());
+        let (records, _) = store.scan(None, None).unwrap();
+        assert_eq!(
+            vec![commitment2],
+            records.iter().map(|(c, _)| *c).collect::<Vec<_>>()
+        );
+        let error = store.scan_since(1, None, None).unwrap_err();
+        assert_eq!(
+            AppError::PrunedRecords("records before height 2 were pruned".to_string()),
+            AppError::find(&error)
+        );
+        assert_eq!(1, store.scan_since(2, None, None).unwrap().0.len());
+
+        // but the pruned records can still be validated and spent, and not added again
+        assert!(store.exists(&commitment1, &commitments_root).unwrap());
+        assert!(store.add(commitment1, record1).is_err());
+        store.spend(&serial_number1).unwrap();
+        let roots = commit(&store, &storage, 3);
+
+        // the trees can still be rebuilt, e.g. after restoring a snapshot
+        assert_eq!(roots, store.restore(3).unwrap());
+
+        // the records up to the snapshot height aren't indexed by height after restoring it
+        let error = store.scan_since(3, None, None).unwrap_err();
+        assert_eq!(
+            AppError::PrunedRecords("records before height 4 were pruned".to_string()),
+            AppError::find(&error)
+        );
+        assert!(store.scan_since(4, None, None).unwrap().0.is_empty());
+
+        // FIXME patching rocksdb weird behavior
+        std::mem::forget(store);
+    }
+
+    // TODO: (check if it's possible) make a test for validating behavior related to spending a non-existant record
+
+    fn new_record() -> (EncryptedRecord, Commitment, SerialNumber) {
+        let rng = &mut rand::thread_rng();
+        let randomizer = Uniform::rand(rng);
+        let nonce = Testnet3::g_scalar_multiply(&randomizer);
+        let record = PublicRecord::from_str(
+            &format!("{{ owner: aleo1330ghze6tqvc0s9vd43mnetxlnyfypgf6rw597gn4723lp2wt5gqfk09ry.private, gates: 5u64.private, token_amount: 100u64.private, _nonce: {nonce}.public }}"),
+        ).unwrap();
+        let program_id = ProgramID::from_str("foo.aleo").unwrap();
+        let name = Identifier::from_str("bar").unwrap();
+        let commitment = record.to_commitment(&program_id, &name).unwrap();
+        let record_ciphertext = record.encrypt(randomizer).unwrap();
+
+        // compute serial number to check for spending status
+        let pk =
+            PrivateKey::from_str("APrivateKey1zkpCT3zCj49nmVoeBXa21EGLjTUc7AKAcMNKLXzP7kc4cgx")
+                .unwrap();
+        let serial_number = vm::compute_serial_number(pk, commitment).unwrap();
+
+        (record_ciphertext, commitment, serial_number)
+    }
+}
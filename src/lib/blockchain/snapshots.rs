@@ -0,0 +1,356 @@
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::merkle::{Hash, StateRoots};
+use anyhow::{anyhow, ensure, Result};
+use rocksdb::IteratorMode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tendermint_proto::abci;
+use tracing::{error, info};
+
+/// Version of the snapshot encoding, to be increased when the chunk or metadata contents change.
+pub const SNAPSHOT_FORMAT: u32 = 3;
+
+/// Approximate size of each snapshot chunk, in bytes. Tendermint accepts chunks of up to 16MB.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Name of the file with the snapshot metadata, written after all the chunks so incomplete snapshots are ignored.
+const METADATA_FILE: &str = "metadata";
+
+/// The columns left out of the snapshots, since their contents can't be checked against the state roots.
+const LOCAL_COLUMNS: [Column; 2] = [Column::Undo, Column::RecordHeights];
+
+/// A key and value of the storage, as included in the snapshot chunks.
+type Entry = (Column, Vec<u8>, Vec<u8>);
+
+/// The snapshot metadata sent to peers along with the snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    /// The state roots at the snapshot height, to check the snapshot against the trusted app hash before restoring it.
+    roots: StateRoots,
+    /// The hashes of each chunk, to check them as they are received.
+    chunk_hashes: Vec<Hash>,
+}
+
+/// A snapshot being restored, with the amount of chunks already applied.
+#[derive(Debug)]
+struct Restore {
+    metadata: Metadata,
+    applied: u32,
+}
+
+/// Manages the snapshots of the application state used by Tendermint state sync, so new nodes can start
+/// from a recent height instead of replaying (and verifying) the whole chain.
+/// Every `interval` blocks the storage is copied to a checkpoint, which is then split into chunks in a background thread.
+/// Only the most recent snapshots are kept. The snapshots include all the stored state except for the undo data of past blocks
+/// and the height index of the records, which are local data that can't be checked against the state roots.
+#[derive(Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+    /// Amount of blocks between snapshots, or 0 to disable them.
+    interval: u64,
+    /// Amount of snapshots to keep.
+    keep: usize,
+    restore: Mutex<Option<Restore>>,
+}
+
+impl Snapshots {
+    pub fn new(dir: PathBuf, interval: u64, keep: usize) -> Self {
+        Self {
+            dir,
+            interval,
+            keep,
+            restore: Mutex::new(None),
+        }
+    }
+
+    /// Take a snapshot of the given storage if one is due at this height. The storage is expected to contain
+    /// the state committed at the height, with the given roots.
+    pub fn maybe_take(&self, storage: &Storage, height: u64, roots: StateRoots) {
+        if self.interval == 0 || height % self.interval != 0 {
+            return;
+        }
+
+        // the checkpoint is created while the block is being committed, so it reflects the state at this height,
+        // but the slower work of splitting it into chunks is done in the background
+        let checkpoint = self.dir.join(format!("checkpoint-{height}"));
+        if let Err(err) = std::fs::create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| storage.checkpoint(&checkpoint))
+        {
+            error!("failed to create checkpoint for snapshot at height {height}: {err}");
+            return;
+        }
+
+        let dir = self.dir.clone();
+        let keep = self.keep;
+        std::thread::spawn(move || {
+            match write_snapshot(&dir, &checkpoint, height, roots) {
+                Ok(chunks) => info!("Created snapshot at height {height} with {chunks} chunks"),
+                Err(err) => error!("failed to create snapshot at height {height}: {err}"),
+            }
+            std::fs::remove_dir_all(&checkpoint).unwrap_or_else(|e| error!("{e}"));
+            prune_snapshots(&dir, keep).unwrap_or_else(|e| error!("{e}"));
+        });
+    }
+
+    /// Return the available snapshots.
+    pub fn list(&self) -> Result<Vec<abci::Snapshot>> {
+        let mut snapshots = vec![];
+        for height in snapshot_heights(&self.dir)? {
+            let metadata_bytes = std::fs::read(self.snapshot_dir(height).join(METADATA_FILE))?;
+            let metadata: Metadata = bincode::deserialize(&metadata_bytes)?;
+            snapshots.push(abci::Snapshot {
+                height,
+                format: SNAPSHOT_FORMAT,
+                chunks: metadata.chunk_hashes.len() as u32,
+                hash: Sha256::digest(&metadata_bytes).to_vec(),
+                metadata: metadata_bytes,
+            });
+        }
+        Ok(snapshots)
+    }
+
+    /// Return the contents of a chunk of a local snapshot.
+    pub fn load_chunk(&self, height: u64, format: u32, chunk: u32) -> Result<Vec<u8>> {
+        ensure!(
+            format == SNAPSHOT_FORMAT,
+            "unknown snapshot format {format}"
+        );
+        Ok(std::fs::read(
+            self.snapshot_dir(height).join(chunk.to_string()),
+        )?)
+    }
+
+    /// Start restoring the given snapshot, after checking that its state roots match the trusted app hash.
+    pub fn offer(&self, snapshot: &abci::Snapshot, app_hash: &[u8]) -> Result<()> {
+        ensure!(
+            snapshot.format == SNAPSHOT_FORMAT,
+            "unknown snapshot format {}",
+            snapshot.format
+        );
+        ensure!(
+            Sha256::digest(&snapshot.metadata).as_slice() == snapshot.hash,
+            "snapshot hash doesn't match its metadata"
+        );
+        let metadata: Metadata = bincode::deserialize(&snapshot.metadata)?;
+        ensure!(
+            metadata.chunk_hashes.len() as u32 == snapshot.chunks,
+            "snapshot chunks don't match its metadata"
+        );
+        ensure!(
+            metadata.roots.app_hash() == app_hash,
+            "snapshot state roots don't match the app hash"
+        );
+
+        *self.restore.lock().unwrap() = Some(Restore {
+            metadata,
+            applied: 0,
+        });
+        Ok(())
+    }
+
+    /// Check that the given chunk is the next one of the snapshot being restored, as listed in its metadata.
+    /// Chunks that fail this check can be fetched again from a different peer.
+    pub fn check_chunk(&self, index: u32, chunk: &[u8]) -> Result<()> {
+        let restore = self.restore.lock().unwrap();
+        let current = restore
+            .as_ref()
+            .ok_or_else(|| anyhow!("there's no snapshot being restored"))?;
+        ensure!(
+            index == current.applied,
+            "expected chunk {} but got {index}",
+            current.applied
+        );
+        let expected_hash = current.metadata.chunk_hashes[index as usize];
+        ensure!(
+            Sha256::digest(chunk).as_slice() == expected_hash,
+            "chunk {index} doesn't match its hash"
+        );
+        Ok(())
+    }
+
+    /// Write the contents of the next chunk of the snapshot being restored to the storage.
+    /// Returns the state roots of the snapshot once all of its chunks are applied, so the restored state can be checked.
+    pub fn apply_chunk(
+        &self,
+        storage: &Storage,
+        index: u32,
+        chunk: &[u8],
+    ) -> Result<Option<StateRoots>> {
+        self.check_chunk(index, chunk)?;
+        let mut restore = self.restore.lock().unwrap();
+        let current = restore
+            .as_mut()
+            .ok_or_else(|| anyhow!("there's no snapshot being restored"))?;
+
+        let entries: Vec<Entry> = bincode::deserialize(chunk)?;
+        let mut batch = Batch::default();
+        for (column, key, value) in entries {
+            ensure!(
+                !LOCAL_COLUMNS.contains(&column),
+                "unexpected {column:?} data in snapshot"
+            );
+            batch.put(column, key, value);
+        }
+        storage.write(batch)?;
+        current.applied += 1;
+
+        if current.applied as usize == current.metadata.chunk_hashes.len() {
+            let roots = current.metadata.roots;
+            *restore = None;
+            Ok(Some(roots))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Discard the snapshot being restored, if any.
+    pub fn abort_restore(&self) {
+        *self.restore.lock().unwrap() = None;
+    }
+
+    fn snapshot_dir(&self, height: u64) -> PathBuf {
+        self.dir.join(height.to_string())
+    }
+}
+
+/// Split the contents of the storage checkpoint into chunks, and write them to the snapshot directory for the given height.
+/// Returns the amount of chunks.
+fn write_snapshot(dir: &Path, checkpoint: &Path, height: u64, roots: StateRoots) -> Result<usize> {
+    let storage = Storage::open(checkpoint)?;
+    let snapshot_dir = dir.join(height.to_string());
+    std::fs::create_dir_all(&snapshot_dir)?;
+
+    let mut chunk_hashes = vec![];
+    let mut entries: Vec<Entry> = vec![];
+    let mut size = 0;
+    let mut write_chunk = |entries: &mut Vec<Entry>| -> Result<()> {
+        let chunk = bincode::serialize(entries)?;
+        std::fs::write(snapshot_dir.join(chunk_hashes.len().to_string()), &chunk)?;
+        chunk_hashes.push(Sha256::digest(&chunk).into());
+        entries.clear();
+        Ok(())
+    };
+
+    for column in Column::ALL
+        .into_iter()
+        .filter(|column| !LOCAL_COLUMNS.contains(column))
+    {
+        for entry in storage.iterator(column, IteratorMode::Start)? {
+            let (key, value) = entry?;
+            size += key.len() + value.len();
+            entries.push((column, key.to_vec(), value.to_vec()));
+            if size >= CHUNK_SIZE {
+                write_chunk(&mut entries)?;
+                size = 0;
+            }
+        }
+    }
+    if !entries.is_empty() {
+        write_chunk(&mut entries)?;
+    }
+
+    let metadata = Metadata {
+        roots,
+        chunk_hashes,
+    };
+    std::fs::write(
+        snapshot_dir.join(METADATA_FILE),
+        bincode::serialize(&metadata)?,
+    )?;
+    Ok(metadata.chunk_hashes.len())
+}
+
+/// Remove all but the given amount of most recent snapshots.
+fn prune_snapshots(dir: &Path, keep: usize) -> Result<()> {
+    let heights = snapshot_heights(dir)?;
+    for height in heights.iter().rev().skip(keep) {
+        std::fs::remove_dir_all(dir.join(height.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Return the heights of the complete snapshots in the given directory, in ascending order.
+fn snapshot_heights(dir: &Path) -> Result<Vec<u64>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut heights: Vec<u64> = std::fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| entry.path().join(METADATA_FILE).exists())
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    heights.sort();
+    Ok(heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+
+    #[test]
+    fn take_and_restore_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::open(dir.path().join("source")).unwrap();
+        let mut batch = Batch::default();
+        batch.put(Column::Records, b"commitment", b"ciphertext");
+        batch.put(Column::Meta, b"key", b"value");
+        batch.put(Column::RecordHeights, b"height commitment", b"");
+        storage.commit(1, batch).unwrap();
+
+        let roots = StateRoots {
+            commitments: [1; 32],
+            serial_numbers: [2; 32],
+            rest: [3; 32],
+            previous: [4; 32],
+        };
+        let snapshots = Snapshots::new(dir.path().join("snapshots"), 1, 1);
+        let checkpoint = dir.path().join("checkpoint");
+        storage.checkpoint(&checkpoint).unwrap();
+        write_snapshot(&snapshots.dir, &checkpoint, 1, roots).unwrap();
+
+        let listed = snapshots.list().unwrap();
+        assert_eq!(1, listed.len());
+        let snapshot = &listed[0];
+        assert_eq!(1, snapshot.height);
+
+        // the snapshot is rejected if it doesn't match the trusted app hash
+        assert!(snapshots.offer(snapshot, b"wrong hash").is_err());
+        snapshots.offer(snapshot, &roots.app_hash()).unwrap();
+
+        // a modified chunk is rejected
+        let target = Storage::open(dir.path().join("target")).unwrap();
+        let chunk = snapshots.load_chunk(1, SNAPSHOT_FORMAT, 0).unwrap();
+        assert!(snapshots.apply_chunk(&target, 0, b"fake chunk").is_err());
+
+        assert_eq!(
+            Some(roots),
+            snapshots.apply_chunk(&target, 0, &chunk).unwrap()
+        );
+        assert_eq!(
+            Some(b"ciphertext".to_vec()),
+            target.get(Column::Records, b"commitment").unwrap()
+        );
+        assert_eq!(
+            Some(b"value".to_vec()),
+            target.get(Column::Meta, b"key").unwrap()
+        );
+        // undo data and the record heights are not part of the snapshot
+        for column in LOCAL_COLUMNS {
+            assert!(target
+                .iterator(column, IteratorMode::Start)
+                .unwrap()
+                .next()
+                .is_none());
+        }
+
+        // chunks can't be applied once the restore is aborted
+        snapshots.offer(snapshot, &roots.app_hash()).unwrap();
+        snapshots.check_chunk(0, &chunk).unwrap();
+        snapshots.abort_restore();
+        assert!(snapshots.apply_chunk(&target, 0, &chunk).is_err());
+    }
+}
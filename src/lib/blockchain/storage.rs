@@ -0,0 +1,289 @@
+use crate::error::AppError;
+use anyhow::{anyhow, ensure, Result};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, WriteBatch, WriteOptions};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The column families of the application database, one for each kind of state kept by the stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Column {
+    /// Record ciphertexts by commitment.
+    Records,
+    /// Spent record serial numbers.
+    SpentSerialNumbers,
+    /// Record commitments indexed by the height of the block that added them.
+    RecordHeights,
+    /// Nodes of the commitment and serial number merkle trees.
+    Merkle,
+    /// Deployed programs and their verifying keys.
+    Programs,
+    /// Known validators by tendermint address.
+    Validators,
+    /// Application metadata, e.g. the last committed height.
+    Meta,
+    /// The previous values of the keys changed at each height, used to roll back blocks.
+    Undo,
+}
+
+/// A change to a key of the storage. A None value deletes the key.
+type Change = (Column, Vec<u8>, Option<Vec<u8>>);
+
+impl Column {
+    pub const ALL: [Column; 8] = [
+        Column::Records,
+        Column::SpentSerialNumbers,
+        Column::RecordHeights,
+        Column::Merkle,
+        Column::Programs,
+        Column::Validators,
+        Column::Meta,
+        Column::Undo,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Column::Records => "records",
+            Column::SpentSerialNumbers => "spent",
+            Column::RecordHeights => "heights",
+            Column::Merkle => "merkle",
+            Column::Programs => "programs",
+            Column::Validators => "validators",
+            Column::Meta => "meta",
+            Column::Undo => "undo",
+        }
+    }
+}
+
+/// The persistent state of the application. All the stores share a single rocksdb database, with a column family
+/// for each kind of data, so the changes of a block can be written in one atomic batch when it's committed.
+/// Either the whole block is persisted or none of it: a crash in the middle of a commit leaves the node at the previous
+/// height, and tendermint replays the missing blocks on restart.
+/// Along with each block, the storage keeps the previous values of the keys it changed, so the state can be rolled back
+/// to a past height.
+#[derive(Debug)]
+pub struct Storage {
+    db: rocksdb::DB,
+}
+
+impl Storage {
+    /// Open the database at the given path, creating it and its column families if necessary.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+
+        let columns = Column::ALL.iter().map(|column| column.name());
+        let db = rocksdb::DB::open_cf(&options, path, columns)?;
+        Ok(Self { db })
+    }
+
+    pub fn get(&self, column: Column, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(self.handle(column)?, key)?)
+    }
+
+    /// Iterate the (key, value) pairs of the given column, yielding an error for the entries that fail to be read.
+    pub fn iterator(
+        &self,
+        column: Column,
+        mode: IteratorMode,
+    ) -> Result<impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>)>> + '_> {
+        Ok(self
+            .db
+            .iterator_cf(self.handle(column)?, mode)
+            .map(|entry| entry.map_err(anyhow::Error::from)))
+    }
+
+    /// Atomically write the changes of the block at the given height, along with the data needed to undo them.
+    pub fn commit(&self, height: u64, mut batch: Batch) -> Result<()> {
+        let undo = batch
+            .changes
+            .iter()
+            .map(|(column, key, _)| -> Result<Change> {
+                Ok((*column, key.clone(), self.get(*column, key)?))
+            })
+            .collect::<Result<Vec<Change>>>()?;
+        batch.put(
+            Column::Undo,
+            height.to_be_bytes(),
+            bincode::serialize(&undo)?,
+        );
+        self.write(batch)
+    }
+
+    /// Undo the changes of the blocks committed after the given height up to the last one, restoring the state as it
+    /// was at that height. Nothing is written unless the undo data of every one of those blocks is available, and then
+    /// all of them are undone in a single atomic batch. Returns the amount of blocks that were rolled back.
+    pub fn rollback(&self, height: u64, last_height: u64) -> Result<u64> {
+        let start = (height + 1).to_be_bytes();
+        let undo_entries: Vec<_> = self
+            .iterator(Column::Undo, IteratorMode::From(&start, Direction::Forward))?
+            .collect::<Result<_>>()?;
+
+        let undo_heights = undo_entries
+            .iter()
+            .map(|(undo_height, _)| Ok(u64::from_be_bytes(undo_height.as_ref().try_into()?)))
+            .collect::<Result<Vec<u64>>>()?;
+        ensure!(
+            undo_heights.into_iter().eq(height + 1..=last_height),
+            AppError::UnavailableHeight(format!(
+                "missing the undo data of the blocks between heights {height} and {last_height}"
+            ))
+        );
+
+        // restore the values from the last block backwards, so each key ends with the value it had before the first undone block
+        let mut batch = Batch::default();
+        for (undo_height, undo) in undo_entries.iter().rev() {
+            let changes: Vec<Change> = bincode::deserialize(undo)?;
+            batch.changes.extend(changes.into_iter().rev());
+            batch.delete(Column::Undo, undo_height);
+        }
+        self.write(batch)?;
+        Ok(undo_entries.len() as u64)
+    }
+
+    /// Drop the undo data of the blocks before the given height, which can't be rolled back anymore.
+    pub fn prune_undo(&self, before: u64) -> Result<()> {
+        let mut batch = Batch::default();
+        for entry in self.iterator(Column::Undo, IteratorMode::Start)? {
+            let (undo_height, _) = entry?;
+            if u64::from_be_bytes(undo_height.as_ref().try_into()?) >= before {
+                break;
+            }
+            batch.delete(Column::Undo, undo_height);
+        }
+        self.write(batch)
+    }
+
+    /// Create a consistent copy of the database at the given path, which must not exist.
+    /// The files of the copy are hard links when possible, so this is cheap even for a big state.
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        rocksdb::checkpoint::Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        Ok(())
+    }
+
+    /// Delete every key of the database.
+    pub fn clear(&self) -> Result<()> {
+        let mut batch = Batch::default();
+        for column in Column::ALL {
+            for entry in self.iterator(column, IteratorMode::Start)? {
+                let (key, _) = entry?;
+                batch.delete(column, key);
+            }
+        }
+        self.write(batch)
+    }
+
+    /// Atomically write the given batch of changes, syncing it to disk before returning.
+    pub fn write(&self, batch: Batch) -> Result<()> {
+        let mut write_batch = WriteBatch::default();
+        for (column, key, value) in batch.changes {
+            match value {
+                Some(value) => write_batch.put_cf(self.handle(column)?, key, value),
+                None => write_batch.delete_cf(self.handle(column)?, key),
+            }
+        }
+
+        let mut options = WriteOptions::default();
+        options.set_sync(true);
+        Ok(self.db.write_opt(write_batch, &options)?)
+    }
+
+    fn handle(&self, column: Column) -> Result<&ColumnFamily> {
+        self.db
+            .cf_handle(column.name())
+            .ok_or_else(|| anyhow!("missing column family {}", column.name()))
+    }
+}
+
+/// A set of changes to be written together to the storage, typically the ones produced by the stores when a block is committed.
+/// Unlike a rocksdb WriteBatch it doesn't need a reference to the database, so stores can build them independently.
+#[derive(Debug, Default)]
+pub struct Batch {
+    changes: Vec<Change>,
+}
+
+impl Batch {
+    pub fn put(&mut self, column: Column, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
+        self.changes
+            .push((column, key.as_ref().to_vec(), Some(value.as_ref().to_vec())));
+    }
+
+    pub fn delete(&mut self, column: Column, key: impl AsRef<[u8]>) {
+        self.changes.push((column, key.as_ref().to_vec(), None));
+    }
+
+    /// Add the changes of another batch to this one.
+    pub fn append(&mut self, mut other: Batch) {
+        self.changes.append(&mut other.changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_blocks() {
+        let storage = Storage::open(".db_test/storage").unwrap();
+
+        let mut batch = Batch::default();
+        batch.put(Column::Meta, b"key", b"one");
+        storage.commit(1, batch).unwrap();
+
+        let mut batch = Batch::default();
+        batch.put(Column::Meta, b"key", b"two");
+        batch.put(Column::Records, b"record", b"ciphertext");
+        storage.commit(2, batch).unwrap();
+
+        let mut batch = Batch::default();
+        batch.put(Column::Meta, b"key", b"three");
+        storage.commit(3, batch).unwrap();
+        assert_eq!(
+            Some(b"three".to_vec()),
+            storage.get(Column::Meta, b"key").unwrap()
+        );
+
+        // roll back two blocks, the record added in the second one is removed
+        assert_eq!(2, storage.rollback(1, 3).unwrap());
+        assert_eq!(
+            Some(b"one".to_vec()),
+            storage.get(Column::Meta, b"key").unwrap()
+        );
+        assert!(storage.get(Column::Records, b"record").unwrap().is_none());
+
+        // nothing to roll back after the last height
+        assert_eq!(0, storage.rollback(1, 1).unwrap());
+
+        assert_eq!(1, storage.rollback(0, 1).unwrap());
+        assert!(storage.get(Column::Meta, b"key").unwrap().is_none());
+    }
+
+    #[test]
+    fn prune_undo_data() {
+        let storage = Storage::open(".db_test/storage_prune").unwrap();
+        for height in 1..=3 {
+            let mut batch = Batch::default();
+            batch.put(Column::Meta, b"key", height.to_string());
+            storage.commit(height, batch).unwrap();
+        }
+
+        // the pruned blocks can't be rolled back, and trying to leaves the state untouched
+        storage.prune_undo(3).unwrap();
+        assert!(storage.rollback(1, 3).is_err());
+        assert_eq!(
+            Some(b"3".to_vec()),
+            storage.get(Column::Meta, b"key").unwrap()
+        );
+
+        assert_eq!(1, storage.rollback(2, 3).unwrap());
+        assert_eq!(
+            Some(b"2".to_vec()),
+            storage.get(Column::Meta, b"key").unwrap()
+        );
+        assert!(storage.rollback(1, 2).is_err());
+        assert_eq!(
+            Some(b"2".to_vec()),
+            storage.get(Column::Meta, b"key").unwrap()
+        );
+    }
+}
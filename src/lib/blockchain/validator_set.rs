@@ -1,13 +1,14 @@
-use std::{
-    collections::{HashMap, HashSet},
-    path::{Path, PathBuf},
-};
+use std::collections::{HashMap, HashSet};
 
-use lib::vm;
+use crate::blockchain::storage::{Batch, Column, Storage};
+use crate::vm;
+use itertools::Itertools;
 use log::{debug, error, warn};
+use sha2::{Digest, Sha256};
 
-use anyhow::{anyhow, Result};
-use lib::validator::{Address, Stake, Validator, VotingPower};
+use crate::validator::{Address, Stake, Validator, VotingPower};
+use anyhow::Result;
+use rocksdb::IteratorMode;
 
 type Fee = u64;
 
@@ -23,10 +24,8 @@ const PROPOSER_REWARD_PERCENTAGE: u64 = 50;
 /// The ValidatorSet exposes methods to collect fees and has logic to distribute them
 /// (in addition to a baseline reward), based on block proposer and voting power.
 /// There are also methods to apply voting power changes on staking transactions.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ValidatorSet {
-    /// Path to the file used to persist the currently known validator list of validator, so the app works across restarts.
-    path: PathBuf,
     /// The currently known validator set, including the terndermint pub key/address to aleo account mapping
     /// and their last known voting power.
     validators: HashMap<Address, Validator>,
@@ -43,31 +42,31 @@ pub struct ValidatorSet {
 }
 
 impl ValidatorSet {
-    /// Create a new validator set. If a previous validators file is found, populate the set with its contents,
-    /// otherwise start with an empty one.
-    pub fn load_or_create(path: &Path) -> Self {
-        let validators = if let Ok(json) = std::fs::read_to_string(path) {
-            serde_json::from_str::<Vec<Validator>>(&json)
-                .expect("validators file content is invalid")
-                .into_iter()
-                .map(|validator| {
-                    debug!("loading validator {}", validator);
-                    (validator.address(), validator)
-                })
-                .collect()
-        } else {
-            HashMap::new()
-        };
+    /// Create a new validator set, populated with the validators previously committed to the storage, if any.
+    pub fn load(storage: &Storage) -> Result<Self> {
+        let validators = storage
+            .iterator(Column::Validators, IteratorMode::Start)?
+            .map(|entry| -> Result<(Address, Validator)> {
+                let (_address, json) = entry?;
+                let validator = serde_json::from_slice::<Validator>(&json)?;
+                debug!("loading validator {}", validator);
+                Ok((validator.address(), validator))
+            })
+            .collect::<Result<_>>()?;
 
-        Self {
-            path: path.into(),
+        Ok(Self {
             validators,
-            current_height: 0,
-            fees: 0,
-            current_proposer: None,
-            current_votes: HashMap::new(),
-            updated_validators: HashSet::new(),
-        }
+            ..Default::default()
+        })
+    }
+
+    /// Return the known validators, sorted by address.
+    pub fn validators(&self) -> Vec<Validator> {
+        self.validators
+            .iter()
+            .sorted_by_key(|(address, _)| *address)
+            .map(|(_, validator)| validator.clone())
+            .collect()
     }
 
     pub fn replace(&mut self, validators: Vec<Validator>) {
@@ -242,19 +241,34 @@ impl ValidatorSet {
         }
     }
 
-    /// Saves the currently known list of validators to disk.
-    pub fn commit(&mut self) -> Result<()> {
-        let validators_vec: Vec<Validator> = self.validators.values().cloned().collect();
-        let json = serde_json::to_string(&validators_vec).expect("couldn't serialize validators");
-        std::fs::write(&self.path, json)
-            .map_err(|e| anyhow!("failed to write validators file {:?} {e}", self.path))
+    /// Returns a hash of the currently known validators and their voting power, sorted by address
+    /// so the result is the same across nodes.
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for (address, validator) in self
+            .validators
+            .iter()
+            .sorted_by_key(|(address, _)| *address)
+        {
+            hasher.update(address);
+            hasher.update(validator.aleo_address.to_string());
+            hasher.update(validator.voting_power.to_be_bytes());
+        }
+        hasher.finalize().to_vec()
+    }
+
+    /// Returns a batch that saves the currently known list of validators, to be written to the storage along with the rest of the block.
+    pub fn commit(&self) -> Result<Batch> {
+        let mut batch = Batch::default();
+        for (address, validator) in &self.validators {
+            batch.put(Column::Validators, address, serde_json::to_vec(validator)?);
+        }
+        Ok(batch)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use assert_fs::NamedTempFile;
-
     use super::*;
 
     #[test]
@@ -275,8 +289,7 @@ mod tests {
         let validator4 = Validator::from_str(tmint4, &aleo4.1.to_string(), 1).unwrap();
 
         // create validator set, set validators with voting power
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         set.replace(vec![
             validator1.clone(),
             validator2.clone(),
@@ -351,8 +364,7 @@ mod tests {
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 1).unwrap();
 
         // create validator set, set validators with voting power
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         set.replace(vec![validator1.clone(), validator2.clone()]);
 
         // tmint1 is proposer and didn't vote
@@ -393,10 +405,8 @@ mod tests {
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 1).unwrap();
         let validators = vec![validator1.clone(), validator2.clone()];
 
-        let tempfile1 = NamedTempFile::new("validators").unwrap();
-        let tempfile2 = NamedTempFile::new("validators").unwrap();
-        let mut set1 = ValidatorSet::load_or_create(tempfile1.path());
-        let mut set2 = ValidatorSet::load_or_create(tempfile2.path());
+        let mut set1 = ValidatorSet::default();
+        let mut set2 = ValidatorSet::default();
         set1.replace(validators.clone());
         set2.replace(validators);
 
@@ -451,8 +461,7 @@ mod tests {
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 1).unwrap();
 
         // create validator set, set validators with voting power
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         set.replace(vec![validator1.clone(), validator2]);
 
         // in genesis there won't be any previous block votes
@@ -486,8 +495,7 @@ mod tests {
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 1).unwrap();
 
         // create validator set, set validators with voting power
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         set.replace(vec![validator1.clone(), validator2]);
 
         // votes/begin block/commit
@@ -505,11 +513,15 @@ mod tests {
         set.begin_block(&validator1.address(), votes, 1);
 
         // add a new validator, update voting power of a previous one
+        let previous_hash = set.hash();
         let stake3 = Stake::new(tmint3, aleo3.1, 1).unwrap();
         let stake2 = Stake::new(tmint2, aleo2.1, 5).unwrap();
         set.apply(stake3.clone());
         set.apply(stake2.clone());
 
+        // voting power changes are reflected in the validator set hash
+        assert_ne!(previous_hash, set.hash());
+
         // pending updates includes the two given
         let mut updates = set.pending_updates();
         updates.sort_by_key(|v| v.voting_power);
@@ -533,8 +545,7 @@ mod tests {
         let validator1 = Validator::from_str(tmint1, &aleo1.1.to_string(), 5).unwrap();
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 5).unwrap();
 
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         set.replace(vec![validator1, validator2.clone()]);
 
         // votes/begin block
@@ -592,8 +603,7 @@ mod tests {
         let validator1 = Validator::from_str(tmint1, &aleo1.1.to_string(), 5).unwrap();
         let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 5).unwrap();
 
-        let tempfile = NamedTempFile::new("validators").unwrap();
-        let mut set = ValidatorSet::load_or_create(tempfile.path());
+        let mut set = ValidatorSet::default();
         let validators = vec![validator1, validator2];
         set.replace(validators);
 
@@ -627,6 +637,26 @@ mod tests {
             .contains("attempted to unstake more voting power than available"));
     }
 
+    #[test]
+    fn load_committed_validators() {
+        let tmint1 = "vM+mkdPMvplfxO7wM57z4FXy0TlBC2Onb+MaqcXE8ig=";
+        let tmint2 = "2HWbuGk04WQm/CrI/0HxoEtjGY0DXp8oMY6RsyrWwbU=";
+        let aleo1 = account_keys();
+        let aleo2 = account_keys();
+        let validator1 = Validator::from_str(tmint1, &aleo1.1.to_string(), 5).unwrap();
+        let validator2 = Validator::from_str(tmint2, &aleo2.1.to_string(), 5).unwrap();
+
+        let storage = Storage::open(".db_test/validators").unwrap();
+        let mut set = ValidatorSet::load(&storage).unwrap();
+        assert_eq!(ValidatorSet::default().hash(), set.hash());
+        set.replace(vec![validator1, validator2]);
+
+        // the validators are only persisted when the commit batch is written
+        assert_ne!(set.hash(), ValidatorSet::load(&storage).unwrap().hash());
+        storage.write(set.commit().unwrap()).unwrap();
+        assert_eq!(set.hash(), ValidatorSet::load(&storage).unwrap().hash());
+    }
+
     pub fn account_keys() -> (vm::ViewKey, vm::Address) {
         let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
         let view_key = vm::ViewKey::try_from(&private_key).unwrap();
@@ -0,0 +1,122 @@
+use crate::merkle::Hash;
+use crate::query::CacheStats;
+use crate::vm;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Remembers the transitions whose proofs were successfully verified, so the same transaction doesn't need to
+/// have them verified again, e.g. when it's delivered in a block after being checked for the mempool.
+/// Proof verification doesn't depend on the ledger state (the verifying keys of a program never change), so it's
+/// safe to skip it; the stateful checks, like the double spend ones, are still run every time.
+/// Once the cache is full the oldest entries are evicted.
+#[derive(Debug)]
+pub struct VerificationCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    keys: HashSet<Hash>,
+    order: VecDeque<Hash>,
+    hits: u64,
+    misses: u64,
+}
+
+impl VerificationCache {
+    /// Create a cache that holds up to the given amount of transitions. A zero capacity disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Return the cache key of the transition, a hash of its whole contents including the proof. The transition id
+    /// is not enough, since it doesn't cover the proof.
+    pub fn key(transition: &vm::Transition) -> Result<Hash> {
+        Ok(Sha256::digest(bincode::serialize(transition)?).into())
+    }
+
+    /// Return whether the transition with the given key was already verified, counting the hit or miss.
+    pub fn contains(&self, key: &Hash) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let found = entries.keys.contains(key);
+        if found {
+            entries.hits += 1;
+        } else {
+            entries.misses += 1;
+        }
+        found
+    }
+
+    /// Return whether the transition with the given key was already verified, without counting it in the stats.
+    /// Used by the background verification, so the stats only reflect the lookups of the transactions validated.
+    pub fn peek(&self, key: &Hash) -> bool {
+        self.entries.lock().unwrap().keys.contains(key)
+    }
+
+    /// Remember that the transition with the given key was successfully verified.
+    pub fn insert(&self, key: Hash) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.keys.insert(key) {
+            return;
+        }
+        entries.order.push_back(key);
+        if entries.order.len() > self.capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.keys.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        CacheStats {
+            hits: entries.hits,
+            misses: entries.misses,
+            size: entries.keys.len() as u64,
+            capacity: self.capacity as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_oldest_entries() {
+        let cache = VerificationCache::new(2);
+        cache.insert([1; 32]);
+        cache.insert([2; 32]);
+        cache.insert([2; 32]);
+        assert!(cache.contains(&[1; 32]));
+
+        cache.insert([3; 32]);
+        assert!(!cache.peek(&[1; 32]));
+        assert!(cache.peek(&[3; 32]));
+        assert!(!cache.contains(&[1; 32]));
+        assert!(cache.contains(&[2; 32]));
+        assert!(cache.contains(&[3; 32]));
+
+        assert_eq!(
+            CacheStats {
+                hits: 3,
+                misses: 1,
+                size: 2,
+                capacity: 2
+            },
+            cache.stats()
+        );
+
+        let disabled = VerificationCache::new(0);
+        disabled.insert([1; 32]);
+        assert!(!disabled.contains(&[1; 32]));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+
+/// The codespace of the errors returned by the abci application, sent along with their code in the responses
+/// so clients can tell them apart from the errors of other tendermint components.
+pub const CODESPACE: &str = "aleo";
+
+/// The reasons the abci application can reject a transaction or a query. Each of them is returned with a stable
+/// numeric code in the response, so clients can decide what to do (e.g. whether to retry) without parsing the log.
+/// Codes are never reused: new kinds of errors get new codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppError {
+    /// An unexpected failure of the application, e.g. a storage error.
+    Internal(String),
+    /// The transaction or query bytes can't be decoded.
+    Malformed(String),
+    /// The same record is spent more than once within the transaction.
+    DuplicateInput(String),
+    /// The input records are not known to exist in the ledger.
+    UnknownInput(String),
+    /// The input records were already spent in the ledger.
+    AlreadySpent(String),
+    /// The input records are spent by another transaction in the mempool that pays the same or a higher fee.
+    PendingConflict(String),
+    /// The transaction executes a program that wasn't deployed.
+    UnknownProgram(String),
+    /// The transaction deploys a program that already exists.
+    ProgramExists(String),
+    /// The proofs or verifying keys of the transaction are invalid.
+    InvalidProof(String),
+    /// The transaction is not consistent with its id or input records.
+    InvalidTransaction(String),
+    /// The transaction has a negative fee.
+    NegativeFee(String),
+    /// The transaction pays less than the minimum fee.
+    InsufficientFee(String),
+    /// The transaction stakes or unstakes credits in a way that's not allowed.
+    InvalidStake(String),
+    /// The queried height is not available.
+    UnavailableHeight(String),
+    /// The transaction exceeds the maximum size.
+    TransactionTooLarge(String),
+    /// The transaction was created for a different chain.
+    WrongChain(String),
+    /// The transaction expired before being included in a block.
+    Expired(String),
+    /// The queried records were pruned by the node, which only keeps the ones of its recent blocks.
+    PrunedRecords(String),
+    /// The transaction references a state root that is unknown or older than the recent ones kept by the node,
+    /// so it can never be accepted and needs to be built again with a recent root.
+    StaleStateRoot(String),
+    /// The transaction is in the legacy encoding, which isn't bound to a chain nor signed by the creators of its
+    /// transitions, so it can never be accepted and needs to be built again.
+    LegacyTransaction(String),
+}
+
+impl AppError {
+    /// Return the code sent in the abci responses for this error. Zero means success, so codes start at one.
+    pub fn code(&self) -> u32 {
+        match self {
+            AppError::Internal(_) => 1,
+            AppError::Malformed(_) => 2,
+            AppError::DuplicateInput(_) => 3,
+            AppError::UnknownInput(_) => 4,
+            AppError::AlreadySpent(_) => 5,
+            AppError::PendingConflict(_) => 6,
+            AppError::UnknownProgram(_) => 7,
+            AppError::ProgramExists(_) => 8,
+            AppError::InvalidProof(_) => 9,
+            AppError::InvalidTransaction(_) => 10,
+            AppError::NegativeFee(_) => 11,
+            AppError::InsufficientFee(_) => 12,
+            AppError::InvalidStake(_) => 13,
+            AppError::UnavailableHeight(_) => 14,
+            AppError::TransactionTooLarge(_) => 15,
+            AppError::WrongChain(_) => 16,
+            AppError::Expired(_) => 17,
+            AppError::PrunedRecords(_) => 18,
+            AppError::StaleStateRoot(_) => 19,
+            AppError::LegacyTransaction(_) => 20,
+        }
+    }
+
+    /// Build the error from the code and log of an abci response. Unknown codes are decoded as internal errors.
+    pub fn from_code(code: u32, message: String) -> Self {
+        match code {
+            2 => AppError::Malformed(message),
+            3 => AppError::DuplicateInput(message),
+            4 => AppError::UnknownInput(message),
+            5 => AppError::AlreadySpent(message),
+            6 => AppError::PendingConflict(message),
+            7 => AppError::UnknownProgram(message),
+            8 => AppError::ProgramExists(message),
+            9 => AppError::InvalidProof(message),
+            10 => AppError::InvalidTransaction(message),
+            11 => AppError::NegativeFee(message),
+            12 => AppError::InsufficientFee(message),
+            13 => AppError::InvalidStake(message),
+            14 => AppError::UnavailableHeight(message),
+            15 => AppError::TransactionTooLarge(message),
+            16 => AppError::WrongChain(message),
+            17 => AppError::Expired(message),
+            18 => AppError::PrunedRecords(message),
+            19 => AppError::StaleStateRoot(message),
+            20 => AppError::LegacyTransaction(message),
+            _ => AppError::Internal(message),
+        }
+    }
+
+    /// Return the error kept in the chain of the given error, or an internal error with its message if there's none.
+    pub fn find(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AppError>())
+            .cloned()
+            .unwrap_or_else(|| AppError::Internal(format!("{error:#}")))
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::Internal(message)
+            | AppError::Malformed(message)
+            | AppError::DuplicateInput(message)
+            | AppError::UnknownInput(message)
+            | AppError::AlreadySpent(message)
+            | AppError::PendingConflict(message)
+            | AppError::UnknownProgram(message)
+            | AppError::ProgramExists(message)
+            | AppError::InvalidProof(message)
+            | AppError::InvalidTransaction(message)
+            | AppError::NegativeFee(message)
+            | AppError::InsufficientFee(message)
+            | AppError::InvalidStake(message)
+            | AppError::UnavailableHeight(message)
+            | AppError::TransactionTooLarge(message)
+            | AppError::WrongChain(message)
+            | AppError::Expired(message)
+            | AppError::PrunedRecords(message)
+            | AppError::StaleStateRoot(message)
+            | AppError::LegacyTransaction(message) => message,
+        }
+    }
+
+    /// Return whether the same request could succeed if sent again later, e.g. after the transaction it conflicts
+    /// with leaves the mempool or the record it spends is committed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            AppError::Internal(_) | AppError::UnknownInput(_) | AppError::PendingConflict(_)
+        )
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn error_codes() {
+        let errors = [
+            AppError::Internal("internal".to_string()),
+            AppError::Malformed("malformed".to_string()),
+            AppError::DuplicateInput("duplicate".to_string()),
+            AppError::UnknownInput("unknown".to_string()),
+            AppError::AlreadySpent("spent".to_string()),
+            AppError::PendingConflict("conflict".to_string()),
+            AppError::UnknownProgram("program".to_string()),
+            AppError::ProgramExists("exists".to_string()),
+            AppError::InvalidProof("proof".to_string()),
+            AppError::InvalidTransaction("transaction".to_string()),
+            AppError::NegativeFee("negative".to_string()),
+            AppError::InsufficientFee("insufficient".to_string()),
+            AppError::InvalidStake("stake".to_string()),
+            AppError::UnavailableHeight("height".to_string()),
+            AppError::TransactionTooLarge("size".to_string()),
+            AppError::WrongChain("chain".to_string()),
+            AppError::Expired("expired".to_string()),
+            AppError::PrunedRecords("pruned".to_string()),
+            AppError::StaleStateRoot("root".to_string()),
+            AppError::LegacyTransaction("legacy".to_string()),
+        ];
+        for (index, error) in errors.iter().enumerate() {
+            // codes are stable, so the order above must match them
+            assert_eq!(index as u32 + 1, error.code());
+            assert_eq!(
+                *error,
+                AppError::from_code(error.code(), error.message().to_string())
+            );
+        }
+        assert_eq!(
+            AppError::Internal("other".to_string()),
+            AppError::from_code(100, "other".to_string())
+        );
+
+        // the error is found even if context was added to it
+        let error = anyhow::Error::from(AppError::AlreadySpent("spent".to_string()))
+            .context("could not verify transaction");
+        assert_eq!(
+            AppError::AlreadySpent("spent".to_string()),
+            AppError::find(&error)
+        );
+
+        let result: anyhow::Result<()> = Err(anyhow!("failed")).context("context");
+        assert_eq!(
+            AppError::Internal("context: failed".to_string()),
+            AppError::find(&result.unwrap_err())
+        );
+    }
+}
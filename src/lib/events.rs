@@ -0,0 +1,70 @@
+/// An attribute of the events emitted by the abci application for the delivered transactions. Tendermint indexes
+/// them, so transactions can be searched with `tx_search` queries on their keys, e.g. `record.commitment='...'`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventKey {
+    /// The type of the event the attribute belongs to.
+    pub event: &'static str,
+    /// The key of the attribute within the event.
+    pub attribute: &'static str,
+}
+
+impl EventKey {
+    /// Return the key to search for the attribute in tendermint queries.
+    pub fn query_key(&self) -> String {
+        format!("{}.{}", self.event, self.attribute)
+    }
+}
+
+/// Id of the transaction. Unlike the rest, it's also emitted when the transaction fails to be delivered.
+pub const TX_ID: EventKey = EventKey {
+    event: "app",
+    attribute: "tx_id",
+};
+
+/// Fee paid by the transaction, in gates.
+pub const FEE: EventKey = EventKey {
+    event: "app",
+    attribute: "fee",
+};
+
+/// Commitment of a record added by the transaction, emitted once per record.
+pub const COMMITMENT: EventKey = EventKey {
+    event: "record",
+    attribute: "commitment",
+};
+
+/// Serial number of a record spent by the transaction, emitted once per record.
+pub const SERIAL_NUMBER: EventKey = EventKey {
+    event: "record",
+    attribute: "serial_number",
+};
+
+/// Id of the program deployed by the transaction.
+pub const PROGRAM_DEPLOYED: EventKey = EventKey {
+    event: "program",
+    attribute: "deployed",
+};
+
+/// Tendermint address, in upper case hex, of the validator credits were staked to.
+pub const STAKE: EventKey = EventKey {
+    event: "credits",
+    attribute: "stake",
+};
+
+/// Tendermint address, in upper case hex, of the validator credits were unstaked from.
+pub const UNSTAKE: EventKey = EventKey {
+    event: "credits",
+    attribute: "unstake",
+};
+
+/// Amount of gates staked or unstaked, emitted along with the validator address.
+pub const STAKE_AMOUNT: EventKey = EventKey {
+    event: "credits",
+    attribute: "amount",
+};
+
+/// Aleo address of the validator credits were staked to or unstaked from.
+pub const STAKE_ALEO_ADDRESS: EventKey = EventKey {
+    event: "credits",
+    attribute: "aleo_address",
+};
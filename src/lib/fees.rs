@@ -0,0 +1,96 @@
+use crate::transaction::Transaction;
+use crate::vm;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Parameters to calculate the minimum fee, in gates, that a transaction has to pay to be accepted by the network.
+/// They are set in the genesis state, and priced so the cost of a transaction grows with the verification work it
+/// requires from the validators. All of them default to zero, i.e. no minimum fee.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeSchedule {
+    /// Fee paid by every transaction.
+    pub base: u64,
+    /// Fee per byte of the encoded transaction.
+    pub per_byte: u64,
+    /// Fee per transition of the transaction, each of them with a proof to verify.
+    pub per_transition: u64,
+    /// Fee per function of a deployed program, each of them with a verifying key.
+    pub per_function: u64,
+    /// Fee per instruction of the deployed or executed functions, as an approximation of the size of their circuits.
+    pub per_instruction: u64,
+}
+
+impl FeeSchedule {
+    /// Return the minimum fee to be paid by the given transaction. The programs of executed functions are obtained
+    /// with the given function, to price the executions by the size of their circuits.
+    pub fn minimum_fee(
+        &self,
+        transaction: &Transaction,
+        get_program: impl Fn(&vm::ProgramID) -> Result<vm::Program>,
+    ) -> Result<u64> {
+        let size = transaction.to_bytes()?.len() as u64;
+        let (transitions, functions, instructions) = match transaction {
+            Transaction::Deployment { program, fee, .. } => {
+                let instructions = program
+                    .functions()
+                    .values()
+                    .map(|function| function.instructions().len() as u64)
+                    .sum();
+                let transitions = fee.iter().count() as u64;
+                (transitions, program.functions().len() as u64, instructions)
+            }
+            Transaction::Execution { transitions, .. } => {
+                let mut instructions = 0;
+                for transition in transitions {
+                    let program = get_program(transition.program_id())?;
+                    let function = program.get_function(transition.function_name())?;
+                    instructions += function.instructions().len() as u64;
+                }
+                (transitions.len() as u64, 0, instructions)
+            }
+        };
+        Ok(self.fee(size, transitions, functions, instructions))
+    }
+
+    fn fee(&self, size: u64, transitions: u64, functions: u64, instructions: u64) -> u64 {
+        self.base
+            .saturating_add(self.per_byte.saturating_mul(size))
+            .saturating_add(self.per_transition.saturating_mul(transitions))
+            .saturating_add(self.per_function.saturating_mul(functions))
+            .saturating_add(self.per_instruction.saturating_mul(instructions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeSchedule;
+
+    #[test]
+    fn fee_schedule() {
+        assert_eq!(0, FeeSchedule::default().fee(1000, 2, 3, 40));
+
+        let schedule = FeeSchedule {
+            base: 10,
+            per_byte: 1,
+            per_transition: 100,
+            per_function: 1000,
+            per_instruction: 5,
+        };
+        assert_eq!(
+            10 + 1000 + 2 * 100 + 3 * 1000 + 40 * 5,
+            schedule.fee(1000, 2, 3, 40)
+        );
+
+        // missing parameters default to zero
+        let schedule: FeeSchedule = serde_json::from_str(r#"{"base": 10}"#).unwrap();
+        assert_eq!(10, schedule.fee(1000, 2, 3, 40));
+
+        // the fee saturates instead of overflowing
+        let schedule = FeeSchedule {
+            per_byte: 2,
+            ..schedule
+        };
+        assert_eq!(u64::MAX, schedule.fee(u64::MAX, 0, 0, 0));
+    }
+}
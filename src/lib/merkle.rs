@@ -0,0 +1,236 @@
+use crate::vm;
+use anyhow::{anyhow, ensure, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Depth of the record commitment and serial number trees. Supports up to 2^32 leaves.
+pub const DEPTH: usize = 32;
+
+/// ABCI proof operation types, used to tag the `proof_ops` included in query responses.
+pub const COMMITMENT_PROOF_OP: &str = "aleo:commitment";
+pub const SERIAL_NUMBER_PROOF_OP: &str = "aleo:serial_number";
+pub const STATE_ROOTS_PROOF_OP: &str = "aleo:state_roots";
+
+pub type Hash = [u8; 32];
+
+/// The roots of the application state, as committed at a given height.
+/// The app hash included in the block headers is calculated from them, so clients can check
+/// that merkle proofs returned by a node are consistent with the consensus.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct StateRoots {
+    /// Root of the tree of record commitments (with their ciphertexts) added to the ledger.
+    pub commitments: Hash,
+    /// Root of the tree of spent record serial numbers.
+    pub serial_numbers: Hash,
+    /// Hash of the rest of the application state (deployed programs with their verifying keys, validators, fee schedule
+    /// and chain id), which is not provable through queries but can be recalculated from a snapshot of the state.
+    pub rest: Hash,
+    /// App hash of the previous block, chaining the state to every block since genesis.
+    pub previous: Hash,
+}
+
+impl StateRoots {
+    pub fn app_hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.commitments);
+        hasher.update(self.serial_numbers);
+        hasher.update(self.rest);
+        hasher.update(self.previous);
+        hasher.finalize().to_vec()
+    }
+}
+
+/// The path from a leaf to the root of an append-only merkle tree.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the leaf in the tree.
+    pub index: u64,
+    /// Sibling hashes from the leaf level up to the root.
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Calculate the root resulting from hashing the given leaf data through this path.
+    pub fn root(&self, leaf: &[u8]) -> Hash {
+        let mut hash = leaf_hash(leaf);
+        let mut index = self.index;
+        for sibling in &self.siblings {
+            hash = if index % 2 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+            index /= 2;
+        }
+        hash
+    }
+
+    pub fn verify(&self, root: &Hash, leaf: &[u8]) -> bool {
+        self.siblings.len() == DEPTH && self.root(leaf) == *root
+    }
+}
+
+/// Merkle proofs returned by a node along with a query response, already checked against the app hash of a block.
+#[derive(Debug, Default)]
+pub struct StateProof {
+    pub roots: Option<StateRoots>,
+    pub commitments: HashMap<Vec<u8>, MerkleProof>,
+    pub serial_numbers: HashMap<Vec<u8>, MerkleProof>,
+}
+
+impl StateProof {
+    /// Build from the (type, key, data) proof operations of an ABCI query response.
+    pub fn from_ops<'a>(ops: impl Iterator<Item = (&'a str, &'a [u8], &'a [u8])>) -> Result<Self> {
+        let mut proof = Self::default();
+        for (op_type, key, data) in ops {
+            match op_type {
+                COMMITMENT_PROOF_OP => {
+                    proof
+                        .commitments
+                        .insert(key.to_vec(), bincode::deserialize(data)?);
+                }
+                SERIAL_NUMBER_PROOF_OP => {
+                    proof
+                        .serial_numbers
+                        .insert(key.to_vec(), bincode::deserialize(data)?);
+                }
+                STATE_ROOTS_PROOF_OP => proof.roots = Some(bincode::deserialize(data)?),
+                _ => return Err(anyhow!("unknown proof operation {op_type}")),
+            }
+        }
+        Ok(proof)
+    }
+
+    /// Fail unless the proven state roots hash to the given app hash.
+    pub fn verify_app_hash(&self, app_hash: &[u8]) -> Result<()> {
+        let roots = self
+            .roots
+            .ok_or_else(|| anyhow!("query response doesn't include the state roots"))?;
+        ensure!(
+            roots.app_hash() == app_hash,
+            "query proof state roots don't match the block app hash"
+        );
+        Ok(())
+    }
+
+    /// Fail unless there's a valid proof that the given record is part of the ledger.
+    pub fn verify_record(
+        &self,
+        commitment: &vm::Field,
+        ciphertext: &vm::EncryptedRecord,
+    ) -> Result<()> {
+        let roots = self
+            .roots
+            .ok_or_else(|| anyhow!("query response doesn't include the state roots"))?;
+        let valid = self
+            .commitments
+            .get(&commitment.to_string().into_bytes())
+            .map_or(false, |proof| {
+                proof.verify(&roots.commitments, &record_leaf(commitment, ciphertext))
+            });
+        ensure!(valid, "invalid or missing proof for record {commitment}");
+        Ok(())
+    }
+
+    /// Fail unless there's a valid proof that the given serial number was spent.
+    pub fn verify_serial_number(&self, serial_number: &vm::Field) -> Result<()> {
+        let roots = self
+            .roots
+            .ok_or_else(|| anyhow!("query response doesn't include the state roots"))?;
+        let key = serial_number.to_string().into_bytes();
+        let valid = self
+            .serial_numbers
+            .get(&key)
+            .map_or(false, |proof| proof.verify(&roots.serial_numbers, &key));
+        ensure!(
+            valid,
+            "invalid or missing proof for serial number {serial_number}"
+        );
+        Ok(())
+    }
+}
+
+/// The leaf data of a record in the commitment tree. The ciphertext is included so clients can verify
+/// the contents of the records returned by a node, and not just their commitments.
+pub fn record_leaf(commitment: &vm::Field, ciphertext: &vm::EncryptedRecord) -> Vec<u8> {
+    [
+        commitment.to_string().into_bytes(),
+        ciphertext.to_string().into_bytes(),
+    ]
+    .concat()
+}
+
+/// Hash the leaf data. Leaves and inner nodes are hashed with a different prefix to prevent
+/// second preimage attacks.
+pub fn leaf_hash(leaf: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The hashes of empty subtrees for each level of the tree, from the leaves (zeroed) up to the root.
+pub fn empty_hashes() -> Vec<Hash> {
+    let mut hashes = vec![[0u8; 32]];
+    while hashes.len() <= DEPTH {
+        let last = hashes[hashes.len() - 1];
+        hashes.push(node_hash(&last, &last));
+    }
+    hashes
+}
+
+/// Calculate the root of a tree with the given leaf hashes, in order, followed by empty leaves.
+pub fn root(leaf_hashes: Vec<Hash>) -> Hash {
+    let empty = empty_hashes();
+    let mut level = leaf_hashes;
+    for empty_hash in empty.iter().take(DEPTH) {
+        if level.len() % 2 == 1 {
+            level.push(*empty_hash);
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| node_hash(&pair[0], &pair[1]))
+            .collect();
+    }
+    level.first().copied().unwrap_or(empty[DEPTH])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_proof() {
+        // a tree with two leaves, prove the second one
+        let empty = empty_hashes();
+        let mut siblings = vec![leaf_hash(b"left")];
+        siblings.extend_from_slice(&empty[1..DEPTH]);
+        let proof = MerkleProof { index: 1, siblings };
+
+        let mut root = node_hash(&leaf_hash(b"left"), &leaf_hash(b"right"));
+        for empty_hash in empty.iter().take(DEPTH).skip(1) {
+            root = node_hash(&root, empty_hash);
+        }
+
+        assert_eq!(
+            root,
+            super::root(vec![leaf_hash(b"left"), leaf_hash(b"right")])
+        );
+        assert!(proof.verify(&root, b"right"));
+        assert!(!proof.verify(&root, b"left"));
+        assert!(!proof.verify(&empty[DEPTH], b"right"));
+
+        // a proof with the wrong index shouldn't verify
+        let proof = MerkleProof { index: 0, ..proof };
+        assert!(!proof.verify(&root, b"right"));
+    }
+}
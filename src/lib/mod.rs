@@ -1,5 +1,10 @@
 use std::{path::PathBuf, str::FromStr};
 
+pub mod blockchain;
+pub mod error;
+pub mod events;
+pub mod fees;
+pub mod merkle;
 pub mod program_file;
 pub mod query;
 pub mod transaction;
@@ -16,7 +16,7 @@ pub struct ProgramFile {
 impl ProgramFile {
     pub fn build(program_source: &str) -> Result<Self> {
         let program = vm::generate_program(program_source)?;
-        let keys = vm::synthesize_program_keys(&program)?;
+        let keys = vm::synthesize_program_keys(&program, &[])?;
 
         Ok(Self { program, keys })
     }
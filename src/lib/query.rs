@@ -1,14 +1,64 @@
-use crate::vm::ProgramID;
+use crate::vm::{Field, ProgramID};
 use serde::{Deserialize, Serialize};
 
+/// The maximum amount of records returned by a records query, regardless of the requested limit, so a single query
+/// can't make a node load and send its whole ledger.
+pub const MAX_RECORDS_PAGE_SIZE: usize = 1000;
+
+/// The queries supported by the ABCI application. They are answered with the state of the last committed block,
+/// or with the one of the height passed along with the query, as long as the node still keeps it.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum AbciQuery {
-    /// Returns all records's ciphertexts from the blockchain
-    GetRecords,
+    /// Returns up to `limit` records's ciphertexts from the blockchain, starting from the given commitment.
+    /// The limit defaults to, and can't exceed, `MAX_RECORDS_PAGE_SIZE`.
+    /// Along with the records, returns the commitment to continue from, if there are more.
+    GetRecords {
+        from: Option<Field>,
+        limit: Option<usize>,
+    },
+    /// Returns up to `limit` records's ciphertexts added to the blockchain since the given height, starting from the
+    /// given commitment within that block, if any. The limit defaults to, and can't exceed, `MAX_RECORDS_PAGE_SIZE`.
+    /// Along with the records, returns the height and commitment to continue from, since a page can end in the
+    /// middle of a block.
+    GetRecordsSince {
+        height: u64,
+        from: Option<Field>,
+        limit: Option<usize>,
+    },
+    /// Returns the ciphertext of the record with the given commitment, if it exists in the blockchain
+    GetRecord { commitment: Field },
     /// Returns all spent records's serial numbers
     GetSpentSerialNumbers,
+    /// Returns whether each of the given serial numbers was spent, as of the last committed block
+    IsSpent { serial_numbers: Vec<Field> },
+    /// Returns the state roots of the last committed block, to be proven against its app hash
+    GetStateRoots,
     /// Returns the program struct given it's id
     GetProgram { program_id: ProgramID },
+    /// Returns the validator set, JSON encoded since the tendermint public keys don't support bincode
+    GetValidators,
+    /// Returns whether the chain requires transactions to reveal their input records, as set on genesis
+    RevealsInputRecords,
+    /// Returns the hit and miss counts of the proof verification cache of the node
+    GetVerificationCacheStats,
+    /// Returns whether the transaction with the given id was accepted to the mempool by the application since the last
+    /// commit or recheck. The mempool kept by tendermint, listed by its `unconfirmed_txs` endpoint, is the source of
+    /// truth for pending transactions: this is only a fallback for the transactions that endpoint doesn't list, and
+    /// can't tell about the ones tendermint evicted without notifying the application.
+    IsPending { transaction_id: String },
+}
+
+/// Usage statistics of the cache of verified transitions kept by a node.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Times a transition was found in the cache, skipping the verification of its proof.
+    pub hits: u64,
+    /// Times a transition wasn't found in the cache and its proof had to be verified.
+    pub misses: u64,
+    /// Amount of transitions in the cache.
+    pub size: u64,
+    /// Maximum amount of transitions kept in the cache.
+    pub capacity: u64,
 }
 
 impl From<AbciQuery> for Vec<u8> {
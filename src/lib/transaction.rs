@@ -1,7 +1,10 @@
+use crate::error::AppError;
 use crate::load_credits;
+use crate::merkle;
 use crate::validator;
 use crate::vm;
-use anyhow::{anyhow, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
+use bincode::Options;
 use log::debug;
 use rand;
 use serde::{Deserialize, Serialize};
@@ -10,8 +13,45 @@ use std::fs;
 use std::path::Path;
 use std::str::FromStr;
 
+/// Maximum size, in bytes, of a serialized transaction accepted by the blockchain. Larger transactions are rejected
+/// before being decoded. Note that tendermint has its own limit, `max_tx_bytes` in its `config.toml`.
+pub const MAX_TRANSACTION_SIZE: usize = 10 * 1024 * 1024;
+
+/// Version of the transaction encoding produced by `Transaction::to_bytes`. The legacy encoding, plain bincode of the
+/// enum, starts with the index of its variant (0 or 1), so versions start at 2 to tell them apart.
+pub const TRANSACTION_VERSION: u8 = 2;
+
+/// Type byte of each kind of transaction in the encoding.
+const DEPLOYMENT_TYPE: u8 = 0;
+const EXECUTION_TYPE: u8 = 1;
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Transaction {
+    Deployment {
+        id: String,
+        chain_id: String,
+        expires_at_height: Option<u64>,
+        program: Box<vm::Program>,
+        verifying_keys: vm::VerifyingKeyMap,
+        fee: Option<vm::Transition>,
+        input_records: InputRecords,
+        signatures: Vec<vm::TransitionSignature>,
+    },
+    Execution {
+        id: String,
+        chain_id: String,
+        expires_at_height: Option<u64>,
+        transitions: Vec<vm::Transition>,
+        input_records: InputRecords,
+        signatures: Vec<vm::TransitionSignature>,
+    },
+}
+
+/// The transactions as encoded in the legacy bincode format, before the versioned envelope: the transaction enum
+/// as it was, without a chain id, expiry height, input records or signatures.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+enum LegacyTransaction {
     Deployment {
         id: String,
         program: Box<vm::Program>,
@@ -24,47 +64,272 @@ pub enum Transaction {
     },
 }
 
+impl From<LegacyTransaction> for Transaction {
+    fn from(legacy: LegacyTransaction) -> Self {
+        match legacy {
+            LegacyTransaction::Deployment {
+                id,
+                program,
+                verifying_keys,
+                fee,
+            } => Transaction::Deployment {
+                id,
+                chain_id: String::new(),
+                expires_at_height: None,
+                program,
+                verifying_keys,
+                fee,
+                input_records: InputRecords::default(),
+                signatures: vec![],
+            },
+            LegacyTransaction::Execution { id, transitions } => Transaction::Execution {
+                id,
+                chain_id: String::new(),
+                expires_at_height: None,
+                transitions,
+                input_records: InputRecords::default(),
+                signatures: vec![],
+            },
+        }
+    }
+}
+
+/// The records spent by a transaction, revealed so validators can check that they exist in the ledger.
+/// Revealing them links the spent serial numbers to the record commitments, so it's only done on chains that require
+/// it on genesis; otherwise the records are empty.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct InputRecords {
+    /// Root of a recent record commitment tree that includes the input records.
+    pub state_root: merkle::Hash,
+    /// Commitment and gamma value of each input record, in the same order as the transaction serial numbers.
+    pub records: Vec<(vm::Field, vm::Group)>,
+}
+
+impl InputRecords {
+    /// Reveal the given input records under the state root, if there is one, or none of them otherwise.
+    fn new(state_root: Option<merkle::Hash>, records: Vec<(vm::Field, vm::Group)>) -> Self {
+        match state_root {
+            Some(state_root) => Self {
+                state_root,
+                records,
+            },
+            None => Self::default(),
+        }
+    }
+}
+
 impl Transaction {
-    // Used to generate deployment of a new program in path
+    /// Encode the transaction to be sent to the blockchain. The encoding is an envelope with the version, the network id,
+    /// the chain id, the optional expiry height and the transaction type, followed by its contents, each of them in the
+    /// canonical byte encoding of snarkVM and prefixed by its length, and by the signatures of its transitions.
+    /// The transaction id is not included, since it's the hash of these bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = Writer {
+            bytes: self.unsigned_bytes()?,
+        };
+        writer.signatures(self.signatures())?;
+        Ok(writer.bytes)
+    }
+
+    /// Encode the transaction without the signatures of its transitions, which sign these bytes.
+    fn unsigned_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = Writer::default();
+        writer.u8(TRANSACTION_VERSION);
+        writer.u16(vm::NETWORK_ID);
+        writer.string(self.chain_id());
+        writer.u8(self.expires_at_height().is_some() as u8);
+        if let Some(height) = self.expires_at_height() {
+            writer.u64(height);
+        }
+        match self {
+            Transaction::Deployment {
+                program,
+                verifying_keys,
+                fee,
+                input_records,
+                ..
+            } => {
+                writer.u8(DEPLOYMENT_TYPE);
+                writer.item(program.as_ref())?;
+                writer.u32(verifying_keys.len() as u32);
+                for (function, verifying_key) in verifying_keys {
+                    writer.item(function)?;
+                    writer.item(verifying_key)?;
+                }
+                writer.u8(fee.is_some() as u8);
+                if let Some(transition) = fee {
+                    writer.item(transition)?;
+                }
+                writer.input_records(input_records)?;
+            }
+            Transaction::Execution {
+                transitions,
+                input_records,
+                ..
+            } => {
+                writer.u8(EXECUTION_TYPE);
+                writer.u32(transitions.len() as u32);
+                for transition in transitions {
+                    writer.item(transition)?;
+                }
+                writer.input_records(input_records)?;
+            }
+        }
+        Ok(writer.bytes)
+    }
+
+    /// Decode a transaction as received by the blockchain, either in the encoding of `to_bytes` or in the legacy
+    /// bincode one. Fails instead of panicking on any malformed input, and doesn't allocate more memory than the
+    /// maximum transaction size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() <= MAX_TRANSACTION_SIZE,
+            AppError::TransactionTooLarge(format!(
+                "transaction of {} bytes exceeds the maximum of {MAX_TRANSACTION_SIZE}",
+                bytes.len()
+            ))
+        );
+        let result: Result<Self> = match bytes.first() {
+            // same encoding as bincode::serialize, but bounded and without accepting garbage after the transaction
+            Some(0) | Some(1) => bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .with_limit(MAX_TRANSACTION_SIZE as u64)
+                .deserialize::<LegacyTransaction>(bytes)
+                .map(Transaction::from)
+                .map_err(anyhow::Error::from),
+            Some(&TRANSACTION_VERSION) => Self::decode(bytes),
+            Some(version) => Err(anyhow!("unsupported transaction version {version}")),
+            None => Err(anyhow!("empty transaction")),
+        };
+        result.map_err(|e| AppError::Malformed(format!("invalid transaction: {e}")).into())
+    }
+
+    /// Decode a transaction in the encoding of `to_bytes`, which is required to be canonical: the transaction has to
+    /// encode back to the same bytes, so there's a single encoding (and id) for the same contents.
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.u8()?;
+        ensure!(
+            version == TRANSACTION_VERSION,
+            "unsupported transaction version {version}"
+        );
+        let network_id = reader.u16()?;
+        ensure!(
+            network_id == vm::NETWORK_ID,
+            "transaction for network {network_id}, expected {}",
+            vm::NETWORK_ID
+        );
+        let chain_id = reader.string()?;
+        let expires_at_height = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u64()?),
+            flag => bail!("invalid expiry flag {flag}"),
+        };
+
+        let transaction = match reader.u8()? {
+            DEPLOYMENT_TYPE => {
+                let program = reader.item()?;
+                let mut verifying_keys = vm::VerifyingKeyMap::new();
+                for _ in 0..reader.u32()? {
+                    verifying_keys.insert(reader.item()?, reader.item()?);
+                }
+                let fee = match reader.u8()? {
+                    0 => None,
+                    1 => Some(reader.item()?),
+                    flag => bail!("invalid fee flag {flag}"),
+                };
+                Transaction::Deployment {
+                    id: String::new(),
+                    chain_id,
+                    expires_at_height,
+                    program: Box::new(program),
+                    verifying_keys,
+                    fee,
+                    input_records: reader.input_records()?,
+                    signatures: reader.signatures()?,
+                }
+            }
+            EXECUTION_TYPE => {
+                let mut transitions = vec![];
+                for _ in 0..reader.u32()? {
+                    transitions.push(reader.item()?);
+                }
+                Transaction::Execution {
+                    id: String::new(),
+                    chain_id,
+                    expires_at_height,
+                    transitions,
+                    input_records: reader.input_records()?,
+                    signatures: reader.signatures()?,
+                }
+            }
+            tx_type => bail!("unknown transaction type {tx_type}"),
+        };
+        reader.finish()?;
+
+        ensure!(
+            transaction.to_bytes()? == bytes,
+            "transaction is not canonically encoded"
+        );
+        transaction.set_hashed_id()
+    }
+
+    // Used to generate deployment of a new program in path, along with the programs it imports
     pub fn deployment(
         path: &Path,
+        imports: &[vm::Program],
         private_key: &vm::PrivateKey,
         fee: Option<(u64, vm::Record)>,
+        state_root: Option<merkle::Hash>,
+        chain_id: &str,
+        expires_at_height: Option<u64>,
     ) -> Result<Self> {
         let program_string = fs::read_to_string(path)?;
         debug!("Deploying program {}", program_string);
         let program = vm::generate_program(&program_string)?;
 
         // generate program keys (proving and verifying) and keep the verifying one for the deploy
-        let verifying_keys = vm::synthesize_program_keys(&program)?
+        let verifying_keys = vm::synthesize_program_keys(&program, imports)?
             .into_iter()
             .map(|(i, keys)| (i, keys.1))
             .collect();
 
-        let fee = Self::execute_fee(private_key, fee, 0)?;
+        let mut fee = Self::execute_fee(private_key, fee, 0)?.unwrap_or_default();
 
         Transaction::Deployment {
             id: "not known yet".to_string(),
-            fee,
+            chain_id: chain_id.to_string(),
+            expires_at_height,
+            fee: fee.transitions.pop(),
             program: Box::new(program),
             verifying_keys,
+            input_records: InputRecords::new(state_root, fee.input_records),
+            signatures: vec![],
         }
+        .sign(&fee.transition_keys)?
         .set_hashed_id()
     }
 
-    // Used to generate an execution of a program in path or an execution of the credits program
+    // Used to generate an execution of a program in path, along with the programs it imports
+    #[allow(clippy::too_many_arguments)]
     pub fn execution(
         program: vm::Program,
+        imports: &[vm::Program],
         function_name: vm::Identifier,
         inputs: &[vm::Value],
         private_key: &vm::PrivateKey,
         requested_fee: Option<(u64, vm::Record)>,
+        state_root: Option<merkle::Hash>,
+        chain_id: &str,
+        expires_at_height: Option<u64>,
     ) -> Result<Self> {
         let rng = &mut rand::thread_rng();
 
-        let (proving_key, _) = vm::synthesize_function_keys(&program, rng, &function_name)?;
-        let mut transitions = vm::execution(
+        let (proving_key, _) =
+            vm::synthesize_function_keys(&program, imports, rng, &function_name)?;
+        let mut output = vm::execution(
             program,
+            imports,
             function_name,
             inputs,
             private_key,
@@ -74,15 +339,24 @@ impl Transaction {
 
         // some amount of fees may be implicit if the execution drops credits. in that case, those credits are
         // subtracted from the fees that were requested to be paid.
-        let implicit_fees = transitions.iter().map(|transition| transition.fee()).sum();
-        if let Some(transition) = Self::execute_fee(private_key, requested_fee, implicit_fees)? {
-            transitions.push(transition);
+        let implicit_fees = output
+            .transitions
+            .iter()
+            .map(|transition| transition.fee())
+            .sum();
+        if let Some(fee) = Self::execute_fee(private_key, requested_fee, implicit_fees)? {
+            output.extend(fee);
         }
 
         Self::Execution {
             id: "not known yet".to_string(),
-            transitions,
+            chain_id: chain_id.to_string(),
+            expires_at_height,
+            transitions: output.transitions,
+            input_records: InputRecords::new(state_root, output.input_records),
+            signatures: vec![],
         }
+        .sign(&output.transition_keys)?
         .set_hashed_id()
     }
 
@@ -91,20 +365,32 @@ impl Transaction {
         inputs: &[vm::Value],
         private_key: &vm::PrivateKey,
         requested_fee: Option<(u64, vm::Record)>,
+        state_root: Option<merkle::Hash>,
+        chain_id: &str,
+        expires_at_height: Option<u64>,
     ) -> Result<Self> {
-        let mut transitions = Self::execute_credits(function_name, inputs, private_key)?;
+        let mut output = Self::execute_credits(function_name, inputs, private_key)?;
 
         // some amount of fees may be implicit if the execution drops credits. in that case, those credits are
         // subtracted from the fees that were requested to be paid.
-        let implicit_fees = transitions.iter().map(|transition| transition.fee()).sum();
-        if let Some(transition) = Self::execute_fee(private_key, requested_fee, implicit_fees)? {
-            transitions.push(transition);
+        let implicit_fees = output
+            .transitions
+            .iter()
+            .map(|transition| transition.fee())
+            .sum();
+        if let Some(fee) = Self::execute_fee(private_key, requested_fee, implicit_fees)? {
+            output.extend(fee);
         }
 
         Self::Execution {
             id: "not known yet".to_string(),
-            transitions,
+            chain_id: chain_id.to_string(),
+            expires_at_height,
+            transitions: output.transitions,
+            input_records: InputRecords::new(state_root, output.input_records),
+            signatures: vec![],
         }
+        .sign(&output.transition_keys)?
         .set_hashed_id()
     }
 
@@ -115,6 +401,33 @@ impl Transaction {
         }
     }
 
+    /// Return the id of the chain the transaction was created for. It's empty for transactions in the legacy encoding.
+    pub fn chain_id(&self) -> &str {
+        match self {
+            Transaction::Deployment { chain_id, .. } => chain_id,
+            Transaction::Execution { chain_id, .. } => chain_id,
+        }
+    }
+
+    /// Return whether the transaction is in the legacy encoding, which has no chain id, expiry height nor signatures.
+    pub fn is_legacy(&self) -> bool {
+        self.chain_id().is_empty()
+            && self.expires_at_height().is_none()
+            && self.signatures().is_empty()
+    }
+
+    /// Return the last block height the transaction can be included in, if it expires.
+    pub fn expires_at_height(&self) -> Option<u64> {
+        match self {
+            Transaction::Deployment {
+                expires_at_height, ..
+            } => *expires_at_height,
+            Transaction::Execution {
+                expires_at_height, ..
+            } => *expires_at_height,
+        }
+    }
+
     pub fn output_records(&self) -> Vec<(vm::Field, vm::EncryptedRecord)> {
         self.transitions()
             .iter()
@@ -123,6 +436,22 @@ impl Transaction {
             .collect()
     }
 
+    /// Return the signatures of the transitions, in the same order as them.
+    pub fn signatures(&self) -> &[vm::TransitionSignature] {
+        match self {
+            Transaction::Deployment { signatures, .. } => signatures,
+            Transaction::Execution { signatures, .. } => signatures,
+        }
+    }
+
+    /// Return the records revealed as inputs of this transaction.
+    pub fn input_records(&self) -> &InputRecords {
+        match self {
+            Transaction::Deployment { input_records, .. } => input_records,
+            Transaction::Execution { input_records, .. } => input_records,
+        }
+    }
+
     /// If the transaction is an execution, return the list of input record serial numbers
     pub fn record_serial_numbers(&self) -> Vec<vm::Field> {
         self.transitions()
@@ -131,7 +460,8 @@ impl Transaction {
             .collect()
     }
 
-    fn transitions(&self) -> Vec<vm::Transition> {
+    /// Return the transitions of the transaction. For deployments it's the fee transition, if present.
+    pub fn transitions(&self) -> Vec<vm::Transition> {
         match self {
             Transaction::Deployment { fee, .. } => {
                 if let Some(transition) = fee {
@@ -152,9 +482,12 @@ impl Transaction {
             Transaction::Deployment { fee, .. } => {
                 fee.as_ref().map_or(0, |transition| *transition.fee())
             }
-            Transaction::Execution { transitions, .. } => transitions
-                .iter()
-                .fold(0, |acc, transition| acc + transition.fee()),
+            // saturating, so a malformed transaction can't make this overflow before it's verified
+            Transaction::Execution { transitions, .. } => {
+                transitions.iter().fold(0i64, |acc, transition| {
+                    acc.saturating_add(*transition.fee())
+                })
+            }
         }
     }
 
@@ -199,15 +532,15 @@ impl Transaction {
         Ok(result)
     }
 
-    /// If there is some required fee, return the transition resulting of executing
-    /// the fee function of the credits program for the requested amount.
+    /// If there is some required fee, return the execution of the fee function of the credits program
+    /// for the requested amount, which has a single transition spending the given record.
     /// The fee function just burns the desired amount of credits, so its effect is just
     /// to produce a difference between the input/output records of its transition.
     fn execute_fee(
         private_key: &vm::PrivateKey,
         requested_fee: Option<(u64, vm::Record)>,
         implicit_fee: i64,
-    ) -> Result<Option<vm::Transition>> {
+    ) -> Result<Option<vm::ExecutionOutput>> {
         if let Some((gates, record)) = requested_fee {
             ensure!(
                 implicit_fee >= 0,
@@ -225,8 +558,7 @@ impl Transaction {
                 vm::Value::from_str(&format!("{gates}u64"))?,
             ];
 
-            let transitions = Self::execute_credits("fee", &inputs, private_key)?;
-            Ok(Some(transitions.first().unwrap().clone()))
+            Self::execute_credits("fee", &inputs, private_key).map(Some)
         } else {
             Ok(None)
         }
@@ -236,7 +568,7 @@ impl Transaction {
         function: &str,
         inputs: &[vm::Value],
         private_key: &vm::PrivateKey,
-    ) -> Result<Vec<vm::Transition>> {
+    ) -> Result<vm::ExecutionOutput> {
         let rng = &mut rand::thread_rng();
         let function = vm::Identifier::from_str(function)?;
         let (program, keys) = load_credits();
@@ -246,6 +578,7 @@ impl Transaction {
 
         vm::execution(
             program,
+            &[],
             function,
             inputs,
             private_key,
@@ -254,16 +587,84 @@ impl Transaction {
         )
     }
 
-    /// Verify that the transaction id is consistent with its contents, by checking it's sha256 hash.
+    /// Verify that the transaction id is consistent with its contents, by checking it's sha256 hash,
+    /// that the revealed input records correspond to the serial numbers spent by its transitions,
+    /// and that the creators of its transitions signed the rest of the transaction.
+    /// Transactions in the legacy encoding have neither these ids nor signatures, so they always fail to verify.
     pub fn verify(&self) -> Result<()> {
         ensure!(
             self.id() == self.hash()?,
             "Corrupted transaction: Inconsistent transaction id"
         );
 
+        // the input records are either not revealed, or revealed for each of the spent serial numbers
+        let serial_numbers = self.record_serial_numbers();
+        let records = &self.input_records().records;
+        ensure!(
+            records.is_empty() || serial_numbers.len() == records.len(),
+            "The transaction input records don't match its serial numbers"
+        );
+        for (serial_number, (commitment, gamma)) in serial_numbers.iter().zip(records) {
+            ensure!(
+                vm::serial_number_from_gamma(*commitment, *gamma)? == *serial_number,
+                "Input record {} doesn't match serial number {}",
+                commitment,
+                serial_number
+            );
+        }
+
+        // the transitions are verified independently from each other, so without these signatures any of them could
+        // be taken out of the transaction, e.g. a fee, and bundled with others. only the creator of a transition knows
+        // the secret key of its public transition key, even if it doesn't spend any records.
+        let transitions = self.transitions();
+        let signatures = self.signatures();
+        ensure!(
+            signatures.len() == transitions.len(),
+            "The transaction transitions don't match its signatures"
+        );
+        let message = self.signed_hash()?;
+        for (transition, signature) in transitions.iter().zip(signatures) {
+            ensure!(
+                vm::verify_transition_signature(transition, &message, signature)?,
+                "Transition {} is not signed by its creator",
+                transition.id()
+            );
+        }
+
         Ok(())
     }
 
+    /// Sign the transaction with the secret keys of its transitions, given in the same order as them.
+    fn sign(mut self, transition_keys: &[vm::Scalar]) -> Result<Self> {
+        let message = self.signed_hash()?;
+        let transitions = self.transitions();
+        ensure!(
+            transitions.len() == transition_keys.len(),
+            "expected {} transition keys, got {}",
+            transitions.len(),
+            transition_keys.len()
+        );
+        let new_signatures = transitions
+            .iter()
+            .zip(transition_keys)
+            .map(|(transition, key)| vm::sign_transition(*key, transition, &message))
+            .collect::<Result<Vec<_>>>()?;
+        match self {
+            Transaction::Deployment {
+                ref mut signatures, ..
+            } => *signatures = new_signatures,
+            Transaction::Execution {
+                ref mut signatures, ..
+            } => *signatures = new_signatures,
+        };
+        Ok(self)
+    }
+
+    /// Calculate the sha256 hash of the transaction encoded without signatures, which is signed by its transitions.
+    fn signed_hash(&self) -> Result<Vec<u8>> {
+        Ok(Sha256::digest(self.unsigned_bytes()?).to_vec())
+    }
+
     /// Hash the contents of the given enum and return it with the hash as its id.
     fn set_hashed_id(mut self) -> Result<Self> {
         let new_id = self.hash()?;
@@ -274,46 +675,9 @@ impl Transaction {
         Ok(self)
     }
 
-    /// Calculate a sha256 hash of the contents of the transaction (dependent on the transaction type)
+    /// Calculate the sha256 hash of the encoded transaction, used as its id.
     fn hash(&self) -> Result<String> {
-        let mut hasher = Sha256::new();
-
-        let variant_code: u8 = match self {
-            Transaction::Deployment { .. } => 0,
-            Transaction::Execution { .. } => 1,
-        };
-        hasher.update(variant_code.to_be_bytes());
-
-        match self {
-            Transaction::Deployment {
-                id: _id,
-                program,
-                verifying_keys,
-                fee,
-            } => {
-                hasher.update(program.id().to_string());
-
-                for (key, value) in verifying_keys.into_iter() {
-                    hasher.update(key.to_string());
-                    hasher.update(serde_json::to_string(value)?);
-                }
-
-                if let Some(fee) = fee {
-                    hasher.update(fee.to_string());
-                }
-            }
-            Transaction::Execution {
-                id: _id,
-                transitions,
-            } => {
-                for transition in transitions.iter() {
-                    hasher.update(serde_json::to_string(transition)?);
-                }
-            }
-        }
-
-        let hash = hasher.finalize().as_slice().to_owned();
-        Ok(hex::encode(hash))
+        Ok(hex::encode(Sha256::digest(self.to_bytes()?)))
     }
 
     // TODO: Move this to validator set/use tendermint-rs structs for pub keys?
@@ -345,18 +709,196 @@ impl std::fmt::Display for Transaction {
             Transaction::Deployment { id, program, .. } => {
                 write!(f, "Deployment({},{})", id, program.id())
             }
-            Transaction::Execution { id, transitions } => {
-                let transition = transitions.first().unwrap();
-                let program_id = transition.program_id();
-                write!(f, "Execution({program_id},{id})")
+            Transaction::Execution {
+                id, transitions, ..
+            } => {
+                // decoded transactions are displayed before being validated, so they could have no transitions
+                match transitions.first() {
+                    Some(transition) => write!(f, "Execution({},{id})", transition.program_id()),
+                    None => write!(f, "Execution({id})"),
+                }
             }
         }
     }
 }
 
+/// Builds the encoding of a transaction.
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend(value.as_bytes());
+    }
+
+    /// Write the snarkVM encoding of the value, prefixed by its length.
+    fn item(&mut self, value: &impl vm::ToBytes) -> Result<()> {
+        let bytes = value.to_bytes_le()?;
+        self.u32(bytes.len() as u32);
+        self.bytes.extend(bytes);
+        Ok(())
+    }
+
+    fn input_records(&mut self, input_records: &InputRecords) -> Result<()> {
+        self.bytes.extend(input_records.state_root);
+        self.u32(input_records.records.len() as u32);
+        for (commitment, gamma) in &input_records.records {
+            self.item(commitment)?;
+            self.item(gamma)?;
+        }
+        Ok(())
+    }
+
+    fn signatures(&mut self, signatures: &[vm::TransitionSignature]) -> Result<()> {
+        self.u32(signatures.len() as u32);
+        for signature in signatures {
+            self.item(&signature.challenge)?;
+            self.item(&signature.response)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the encoding of a transaction, failing if it's shorter than expected.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        ensure!(length <= self.bytes.len(), "unexpected end of transaction");
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let length = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(length)?.to_vec())?)
+    }
+
+    /// Read a length prefixed value in the snarkVM encoding.
+    fn item<T: vm::FromBytes>(&mut self) -> Result<T> {
+        let length = self.u32()? as usize;
+        T::from_bytes_le(self.take(length)?)
+    }
+
+    fn input_records(&mut self) -> Result<InputRecords> {
+        let state_root = self.take(32)?.try_into()?;
+        let mut records = vec![];
+        for _ in 0..self.u32()? {
+            records.push((self.item()?, self.item()?));
+        }
+        Ok(InputRecords {
+            state_root,
+            records,
+        })
+    }
+
+    fn signatures(&mut self) -> Result<Vec<vm::TransitionSignature>> {
+        let mut signatures = vec![];
+        for _ in 0..self.u32()? {
+            signatures.push(vm::TransitionSignature {
+                challenge: self.item()?,
+                response: self.item()?,
+            });
+        }
+        Ok(signatures)
+    }
+
+    /// Fail if there are bytes left after the transaction.
+    fn finish(&self) -> Result<()> {
+        ensure!(
+            self.bytes.is_empty(),
+            "unexpected {} bytes after the transaction",
+            self.bytes.len()
+        );
+        Ok(())
+    }
+}
+
+/// Build a transaction that mints a record of the records program, owned by the given key, in the given chain.
+/// Shared by the tests of the library modules that need a valid transaction.
+#[cfg(test)]
+pub(crate) fn mint(
+    private_key: &vm::PrivateKey,
+    chain_id: &str,
+    expires_at_height: Option<u64>,
+) -> Transaction {
+    let view_key = vm::ViewKey::try_from(private_key).unwrap();
+    let address = vm::Address::try_from(&view_key).unwrap();
+    let program = vm::generate_program(include_str!("../../aleo/records.aleo")).unwrap();
+    Transaction::execution(
+        program,
+        &[],
+        vm::Identifier::from_str("mint").unwrap(),
+        &[
+            vm::u64_to_value(10),
+            vm::Value::from_str(&address.to_string()).unwrap(),
+        ],
+        private_key,
+        None,
+        None,
+        chain_id,
+        expires_at_height,
+    )
+    .unwrap()
+}
+
+/// Encode the transitions of a transaction as an execution in the legacy encoding, as sent by older clients.
+#[cfg(test)]
+pub(crate) fn legacy_bytes(transaction: &Transaction) -> Vec<u8> {
+    bincode::serialize(&LegacyTransaction::Execution {
+        id: transaction.id().to_string(),
+        transitions: transaction.transitions(),
+    })
+    .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::transaction::Transaction;
+    use crate::transaction::{legacy_bytes, mint, InputRecords, Transaction, TRANSACTION_VERSION};
+    use crate::vm;
+    use std::str::FromStr;
 
     #[test]
     fn convert_validator_address() {
@@ -368,4 +910,226 @@ mod tests {
 
         assert!(Transaction::validator_address_from_numbers(h, l).unwrap() == pub_key);
     }
+
+    #[test]
+    fn sign_transitions() {
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let view_key = vm::ViewKey::try_from(&private_key).unwrap();
+        let address = vm::Address::try_from(&view_key).unwrap();
+        let program = vm::generate_program(include_str!("../../aleo/records.aleo")).unwrap();
+        let (proving_key, _) = vm::synthesize_function_keys(
+            &program,
+            &[],
+            &mut rand::thread_rng(),
+            &vm::Identifier::from_str("mint").unwrap(),
+        )
+        .unwrap();
+        let output = vm::execution(
+            program,
+            &[],
+            vm::Identifier::from_str("mint").unwrap(),
+            &[
+                vm::u64_to_value(10),
+                vm::Value::from_str(&address.to_string()).unwrap(),
+            ],
+            &private_key,
+            &mut rand::thread_rng(),
+            proving_key,
+        )
+        .unwrap();
+        let transition = &output.transitions[0];
+        let key = output.transition_keys[0];
+
+        let signature = vm::sign_transition(key, transition, b"transaction").unwrap();
+        assert!(vm::verify_transition_signature(transition, b"transaction", &signature).unwrap());
+
+        // the signature is not valid for another message, and the transition can't be signed with another key
+        assert!(!vm::verify_transition_signature(transition, b"other", &signature).unwrap());
+        let other_key = vm::Scalar::from_str("123scalar").unwrap();
+        assert!(vm::sign_transition(other_key, transition, b"transaction").is_err());
+    }
+
+    #[test]
+    fn rebundle_recordless_execution() {
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let view_key = vm::ViewKey::try_from(&private_key).unwrap();
+        let address = vm::Address::try_from(&view_key).unwrap();
+        let program = vm::generate_program(include_str!("../../aleo/records.aleo")).unwrap();
+
+        // minting spends no records, so nothing but the transition signature binds it to the transaction
+        let transaction = Transaction::execution(
+            program,
+            &[],
+            vm::Identifier::from_str("mint").unwrap(),
+            &[
+                vm::u64_to_value(10),
+                vm::Value::from_str(&address.to_string()).unwrap(),
+            ],
+            &private_key,
+            None,
+            None,
+            "test-chain",
+            None,
+        )
+        .unwrap();
+        transaction.verify().unwrap();
+
+        // someone else pays a fee to get the same execution included in a transaction of their own
+        let other_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let other_view_key = vm::ViewKey::try_from(&other_key).unwrap();
+        let other_address = vm::Address::try_from(&other_view_key).unwrap();
+        let (_, record) =
+            vm::mint_record("credits.aleo", "credits", &other_address, 10, 123).unwrap();
+        let record = record.decrypt(&other_view_key).unwrap();
+        let fee = Transaction::execute_fee(&other_key, Some((5, record)), 0)
+            .unwrap()
+            .unwrap();
+
+        let mut transitions = transaction.transitions();
+        transitions.extend(fee.transitions.clone());
+        let mut rebundled = Transaction::Execution {
+            id: String::new(),
+            chain_id: "test-chain".to_string(),
+            expires_at_height: None,
+            transitions,
+            input_records: InputRecords {
+                state_root: Default::default(),
+                records: fee.input_records,
+            },
+            signatures: vec![],
+        };
+
+        // the fee payer can only sign their own transition, and the original signature is over another transaction
+        let message = rebundled.signed_hash().unwrap();
+        let fee_signature =
+            vm::sign_transition(fee.transition_keys[0], &fee.transitions[0], &message).unwrap();
+        if let Transaction::Execution { signatures, .. } = &mut rebundled {
+            *signatures = vec![transaction.signatures()[0], fee_signature];
+        }
+        let rebundled = rebundled.set_hashed_id().unwrap();
+        let error = rebundled.verify().unwrap_err();
+        assert!(error.to_string().contains("is not signed by its creator"));
+
+        // nor can the execution be included with no signature at all
+        let mut unsigned = rebundled;
+        if let Transaction::Execution { signatures, .. } = &mut unsigned {
+            signatures.truncate(1);
+        }
+        let unsigned = unsigned.set_hashed_id().unwrap();
+        assert!(unsigned.verify().is_err());
+    }
+
+    #[test]
+    fn reveal_nested_call_records() {
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let view_key = vm::ViewKey::try_from(&private_key).unwrap();
+        let records_program =
+            vm::generate_program(include_str!("../../aleo/records.aleo")).unwrap();
+        let calls_program = vm::generate_program(include_str!("../../aleo/calls.aleo")).unwrap();
+
+        let minted = mint(&private_key, "test-chain", None);
+        let record = minted.output_records()[0].1.decrypt(&view_key).unwrap();
+
+        // the record is spent by the transition of the called function, not by the one of the root function
+        let transaction = Transaction::execution(
+            calls_program.clone(),
+            &[records_program.clone()],
+            vm::Identifier::from_str("consume").unwrap(),
+            &[vm::Value::Record(record.clone())],
+            &private_key,
+            None,
+            Some(Default::default()),
+            "test-chain",
+            None,
+        )
+        .unwrap();
+        assert_eq!(2, transaction.transitions().len());
+        assert_eq!(1, transaction.record_serial_numbers().len());
+        assert_eq!(1, transaction.input_records().records.len());
+        assert_eq!(
+            minted.output_records()[0].0,
+            transaction.input_records().records[0].0
+        );
+        Transaction::from_bytes(&transaction.to_bytes().unwrap())
+            .unwrap()
+            .verify()
+            .unwrap();
+
+        // without a state root the input records are not revealed
+        let transaction = Transaction::execution(
+            calls_program,
+            &[records_program],
+            vm::Identifier::from_str("consume").unwrap(),
+            &[vm::Value::Record(record)],
+            &private_key,
+            None,
+            None,
+            "test-chain",
+            None,
+        )
+        .unwrap();
+        assert_eq!(1, transaction.record_serial_numbers().len());
+        assert!(transaction.input_records().records.is_empty());
+        transaction.verify().unwrap();
+    }
+
+    #[test]
+    fn encode_transaction() {
+        let private_key = vm::PrivateKey::new(&mut rand::thread_rng()).unwrap();
+        let transaction = mint(&private_key, "test-chain", None);
+
+        let bytes = transaction.to_bytes().unwrap();
+        assert_eq!(TRANSACTION_VERSION, bytes[0]);
+        let decoded = Transaction::from_bytes(&bytes).unwrap();
+        decoded.verify().unwrap();
+        assert_eq!(transaction.id(), decoded.id());
+        assert_eq!(bytes, decoded.to_bytes().unwrap());
+
+        // a different version, network or transaction type is rejected
+        let type_index = 1 + 2 + 4 + "test-chain".len() + 1;
+        for index in [0, 1, 2, type_index] {
+            let mut modified = bytes.clone();
+            modified[index] ^= 0x10;
+            assert!(Transaction::from_bytes(&modified).is_err());
+        }
+
+        // the chain id is part of the transaction id
+        let mut other_chain = transaction.clone();
+        if let Transaction::Execution { chain_id, .. } = &mut other_chain {
+            *chain_id = "other-chain".to_string();
+        }
+        assert!(other_chain.verify().is_err());
+        let other_chain = other_chain.set_hashed_id().unwrap();
+        assert_ne!(transaction.id(), other_chain.id());
+
+        // so is the expiry height, which is kept through the encoding
+        let mut expiring = transaction.clone();
+        if let Transaction::Execution {
+            expires_at_height, ..
+        } = &mut expiring
+        {
+            *expires_at_height = Some(100);
+        }
+        assert!(expiring.verify().is_err());
+        let expiring = expiring.set_hashed_id().unwrap();
+        assert_ne!(transaction.id(), expiring.id());
+        let decoded = Transaction::from_bytes(&expiring.to_bytes().unwrap()).unwrap();
+        assert_eq!(Some(100), decoded.expires_at_height());
+        assert_eq!(expiring.id(), decoded.id());
+
+        // transactions in the legacy encoding, the bincode of the previous enum, can still be decoded
+        assert!(!transaction.is_legacy());
+        let legacy = legacy_bytes(&transaction);
+        assert_eq!(1, legacy[0]);
+        let decoded = Transaction::from_bytes(&legacy).unwrap();
+        assert!(decoded.is_legacy());
+        assert_eq!(transaction.id(), decoded.id());
+        assert_eq!("", decoded.chain_id());
+        assert_eq!(None, decoded.expires_at_height());
+        assert_eq!(transaction.transitions(), decoded.transitions());
+        assert!(decoded.input_records().records.is_empty());
+
+        // but they aren't signed, so they don't verify
+        assert!(decoded.verify().is_err());
+    }
 }
@@ -4,6 +4,7 @@ use anyhow::{anyhow, ensure, Result};
 use log::debug;
 use serde::{Deserialize, Serialize};
 
+use crate::fees::FeeSchedule;
 use crate::vm;
 
 pub type VotingPower = u64;
@@ -28,8 +29,17 @@ pub struct Stake {
 
 #[derive(Deserialize, Serialize)]
 pub struct GenesisState {
+    /// The chain id of the tendermint genesis, which transactions need to include to be accepted.
+    #[serde(default)]
+    pub chain_id: String,
     pub records: Vec<(vm::Field, vm::EncryptedRecord)>,
     pub validators: Vec<Validator>,
+    #[serde(default)]
+    pub fees: FeeSchedule,
+    /// Whether transactions need to reveal the commitment and gamma of their input records, so validators can check
+    /// that the records exist in the ledger. Off by default, since it links the spent serial numbers to the records.
+    #[serde(default)]
+    pub reveal_input_records: bool,
 }
 
 impl Validator {
@@ -104,6 +114,16 @@ impl Stake {
     pub fn validator_address(&self) -> Address {
         pub_key_to_address(&self.pub_key)
     }
+
+    /// Return the aleo account of the validator.
+    pub fn aleo_address(&self) -> vm::Address {
+        self.aleo_address
+    }
+
+    /// Return the amount of gates staked, negative if they are unstaked.
+    pub fn gates_delta(&self) -> i64 {
+        self.gates_delta
+    }
 }
 
 impl std::hash::Hash for Validator {
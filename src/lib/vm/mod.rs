@@ -1,6 +1,6 @@
 /// Library for interfacing with the VM, and generating Transactions
 ///
-use std::{ops::Deref, str::FromStr, sync::Arc};
+use std::{collections::HashMap, ops::Deref, str::FromStr, sync::Arc};
 
 use anyhow::{anyhow, bail, ensure, Result};
 use indexmap::IndexMap;
@@ -8,12 +8,13 @@ use log::debug;
 use parking_lot::{lock_api::RwLock, RawRwLock};
 use rand::{rngs::ThreadRng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use snarkvm::{
     circuit::AleoV0,
     console::types::string::Integer,
     prelude::{
-        Balance, CallStack, Environment, Itertools, Literal, Network, One, Owner, Plaintext,
-        Testnet3, ToBits, ToField, Uniform, I64,
+        Balance, CallStack, Environment, InputID, Itertools, Literal, Network, One, Owner,
+        Plaintext, Testnet3, ToBits, ToField, Uniform, I64,
     },
 };
 
@@ -30,6 +31,8 @@ pub type EncryptedRecord = snarkvm::prelude::Record<Testnet3, Ciphertext>;
 pub type ViewKey = snarkvm::prelude::ViewKey<Testnet3>;
 pub type PrivateKey = snarkvm::prelude::PrivateKey<Testnet3>;
 pub type Field = snarkvm::prelude::Field<Testnet3>;
+pub type Group = snarkvm::prelude::Group<Testnet3>;
+pub type Scalar = snarkvm::prelude::Scalar<Testnet3>;
 pub type Origin = snarkvm::prelude::Origin<Testnet3>;
 pub type Output = snarkvm::prelude::Output<Testnet3>;
 pub type ProgramID = snarkvm::prelude::ProgramID<Testnet3>;
@@ -40,6 +43,12 @@ pub type Transition = snarkvm::prelude::Transition<Testnet3>;
 pub type VerifyingKeyMap = IndexMap<Identifier, VerifyingKey>;
 pub type KeyPairMap = IndexMap<Identifier, (ProvingKey, VerifyingKey)>;
 
+/// The canonical byte encoding of snarkVM types.
+pub use snarkvm::prelude::{FromBytes, ToBytes};
+
+/// Id of the snarkVM network, included in the encoded transactions.
+pub const NETWORK_ID: u16 = <Testnet3 as Network>::ID;
+
 /// Basic deployment validations
 pub fn verify_deployment(program: &Program, verifying_keys: VerifyingKeyMap) -> Result<()> {
     // Ensure the deployment contains verifying keys.
@@ -171,14 +180,15 @@ pub fn verify_execution(transition: &Transition, verifying_keys: &VerifyingKeyMa
 
 /// Generate proving and verifying keys for each function in the given program,
 /// and return them in a function name -> (proving key, verifying key) map.
-pub fn synthesize_program_keys(program: &Program) -> Result<KeyPairMap> {
+/// The programs imported by it, directly or indirectly, need to be passed along.
+pub fn synthesize_program_keys(program: &Program, imports: &[Program]) -> Result<KeyPairMap> {
     let mut verifying_keys = IndexMap::new();
 
     for function_name in program.functions().keys() {
         let rng = &mut rand::thread_rng();
         verifying_keys.insert(
             *function_name,
-            synthesize_function_keys(program, rng, function_name)?,
+            synthesize_function_keys(program, imports, rng, function_name)?,
         );
     }
 
@@ -188,10 +198,11 @@ pub fn synthesize_program_keys(program: &Program) -> Result<KeyPairMap> {
 /// Generate proving and verifying keys for the given function.
 pub fn synthesize_function_keys(
     program: &Program,
+    imports: &[Program],
     rng: &mut ThreadRng,
     function_name: &Identifier,
 ) -> Result<(ProvingKey, VerifyingKey)> {
-    let stack = stack::new_init(program)?;
+    let stack = stack::new_init(program, imports)?;
     stack.synthesize_key::<AleoV0, _>(function_name, rng)?;
     let proving_key = stack.proving_keys.read().get(function_name).cloned();
     let proving_key = proving_key.ok_or_else(|| anyhow!("proving key not found for identifier"))?;
@@ -209,14 +220,36 @@ pub fn generate_program(program_string: &str) -> Result<Program> {
     Program::from_str(program_string)
 }
 
+/// The result of executing a program function: its transitions, including the ones of the functions of imported
+/// programs it calls, along with the data of the caller needed to include them in a transaction.
+#[derive(Debug, Default)]
+pub struct ExecutionOutput {
+    pub transitions: Vec<Transition>,
+    /// The commitment and gamma of the records spent by the transitions, in the order of their serial numbers.
+    pub input_records: Vec<(Field, Group)>,
+    /// The secret key of each transition, in the same order as them, to sign the transaction that includes them.
+    pub transition_keys: Vec<Scalar>,
+}
+
+impl ExecutionOutput {
+    /// Append the transitions of another execution, e.g. the one paying the fee.
+    pub fn extend(&mut self, other: ExecutionOutput) {
+        self.transitions.extend(other.transitions);
+        self.input_records.extend(other.input_records);
+        self.transition_keys.extend(other.transition_keys);
+    }
+}
+
+/// Execute the given program function.
 pub fn execution(
     program: Program,
+    imports: &[Program],
     function_name: Identifier,
     inputs: &[Value],
     private_key: &PrivateKey,
     rng: &mut ThreadRng,
     key: ProvingKey,
-) -> Result<Vec<Transition>> {
+) -> Result<ExecutionOutput> {
     ensure!(
         !Program::is_coinbase(program.id(), &function_name),
         "Coinbase functions cannot be called"
@@ -232,11 +265,29 @@ pub fn execution(
         program, function_name, inputs
     );
 
-    let stack = stack::new_init(&program)?;
+    let stack = stack::new_init(&program, imports)?;
 
     stack.insert_proving_key(&function_name, key)?;
+    synthesize_external_keys(&stack, rng)?;
 
     let authorization = stack.authorize::<AleoV0, _>(private_key, function_name, inputs, rng)?;
+
+    // the request of each transition reveals the commitment and gamma of its input records, which are needed to
+    // link them to the spent serial numbers, and holds the transition secret key. They are collected before the
+    // execution consumes the authorization.
+    let requests = authorization.to_vec_deque();
+    let request_keys: Vec<Scalar> = requests.iter().map(|request| *request.tsk()).collect();
+    let revealed: HashMap<Field, (Field, Group)> = requests
+        .iter()
+        .flat_map(|request| request.input_ids())
+        .filter_map(|input_id| match input_id {
+            InputID::Record(commitment, gamma, serial_number, _) => {
+                Some((*serial_number, (*commitment, *gamma)))
+            }
+            _ => None,
+        })
+        .collect();
+
     let execution: Arc<RwLock<RawRwLock, _>> = Arc::new(RwLock::new(Execution::new()));
 
     // Execute the circuit.
@@ -246,8 +297,44 @@ pub fn execution(
     )?;
 
     let execution = execution.read().clone();
+    let transitions: Vec<Transition> = execution.into_transitions().collect();
+    let input_records = transitions
+        .iter()
+        .flat_map(Transition::serial_numbers)
+        .map(|serial_number| {
+            revealed.get(serial_number).copied().ok_or_else(|| {
+                anyhow!("input record of serial number {serial_number} not found in the execution")
+            })
+        })
+        .collect::<Result<_>>()?;
+    let transition_keys = transitions
+        .iter()
+        .map(|transition| {
+            request_keys
+                .iter()
+                .find(|key| Testnet3::g_scalar_multiply(key) == *transition.tpk())
+                .copied()
+                .ok_or_else(|| anyhow!("secret key of transition {} not found", transition.id()))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(ExecutionOutput {
+        transitions,
+        input_records,
+        transition_keys,
+    })
+}
 
-    Ok(execution.into_transitions().collect())
+/// Generate the proving keys of the functions of the programs imported by the given stack, so they can be called
+/// during its execution.
+fn synthesize_external_keys(stack: &stack::Stack, rng: &mut ThreadRng) -> Result<()> {
+    for external_stack in stack.external_stacks.values() {
+        for function_name in external_stack.program.functions().keys() {
+            external_stack.synthesize_key::<AleoV0, _>(function_name, rng)?;
+        }
+        synthesize_external_keys(external_stack, rng)?;
+    }
+    Ok(())
 }
 
 /// Generate a record for a specific program with the given attributes,
@@ -289,10 +376,80 @@ pub fn gates(record: &Record) -> u64 {
 
 /// A helper method to derive the serial number from the private key and commitment.
 pub fn compute_serial_number(private_key: PrivateKey, commitment: Field) -> Result<Field> {
+    let gamma = compute_gamma(private_key, commitment)?;
+    serial_number_from_gamma(commitment, gamma)
+}
+
+/// Compute the gamma value of a record, used to derive its serial number. Revealing it links the
+/// serial number to the record commitment without disclosing the private key of the owner.
+pub fn compute_gamma(private_key: PrivateKey, commitment: Field) -> Result<Group> {
     // Compute the generator `H` as `HashToGroup(commitment)`.
     let h = Testnet3::hash_to_group_psd2(&[Testnet3::serial_number_domain(), commitment])?;
     // Compute `gamma` as `sk_sig * H`.
-    let gamma = h * private_key.sk_sig();
+    Ok(h * private_key.sk_sig())
+}
+
+/// A Schnorr signature made with the secret key of a transition (`tsk`), which only its creator knows. Since the
+/// transition public key is `tpk = tsk * G` and it's bound to the transition proof, the signature shows that the
+/// creator of the transition authorized the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransitionSignature {
+    pub challenge: Scalar,
+    pub response: Scalar,
+}
+
+/// Sign the message with the secret key of the given transition.
+pub fn sign_transition(
+    transition_key: Scalar,
+    transition: &Transition,
+    message: &[u8],
+) -> Result<TransitionSignature> {
+    ensure!(
+        Testnet3::g_scalar_multiply(&transition_key) == *transition.tpk(),
+        "the key doesn't belong to transition {}",
+        transition.id()
+    );
+
+    // Compute the nonce `R` as `k * G`, for a random `k`.
+    let k = Scalar::rand(&mut rand::thread_rng());
+    let challenge =
+        transition_signature_challenge(transition, Testnet3::g_scalar_multiply(&k), message)?;
+    // Compute the response as `k - challenge * tsk`, so `R` can be recovered as `response * G + challenge * tpk`.
+    let response = k - challenge * transition_key;
+    Ok(TransitionSignature {
+        challenge,
+        response,
+    })
+}
+
+/// Return whether the signature of the message was made with the secret key of the given transition.
+pub fn verify_transition_signature(
+    transition: &Transition,
+    message: &[u8],
+    signature: &TransitionSignature,
+) -> Result<bool> {
+    let nonce =
+        Testnet3::g_scalar_multiply(&signature.response) + *transition.tpk() * signature.challenge;
+    Ok(transition_signature_challenge(transition, nonce, message)? == signature.challenge)
+}
+
+/// Compute the challenge of a transition signature as `Hash(R, tpk, tcm, Hash(message))`.
+fn transition_signature_challenge(
+    transition: &Transition,
+    nonce: Group,
+    message: &[u8],
+) -> Result<Scalar> {
+    let message = Testnet3::hash_bhp1024(&message.to_bits_le())?;
+    Testnet3::hash_to_scalar_psd4(&[
+        nonce.to_x_coordinate(),
+        transition.tpk().to_x_coordinate(),
+        **transition.tcm(),
+        message,
+    ])
+}
+
+/// Derive the serial number of a record from its commitment and gamma value.
+pub fn serial_number_from_gamma(commitment: Field, gamma: Group) -> Result<Field> {
     // Compute `sn_nonce` as `Hash(COFACTOR * gamma)`.
     let sn_nonce = Testnet3::hash_to_scalar_psd2(&[
         Testnet3::serial_number_domain(),
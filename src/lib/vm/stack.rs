@@ -1,5 +1,5 @@
 use super::Program;
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use snarkvm::prelude::{RegisterTypes, Testnet3, UniversalSRS};
 /// This module includes helper functions initially taken from SnarkVM's Stack struct.
 /// The goal is to progressively remove the dependency on that struct.
@@ -10,7 +10,7 @@ pub type Stack = snarkvm::prelude::Stack<Testnet3>;
 /// This function creates and initializes a `Stack` struct for a given program on the fly, providing functionality
 /// related to Programs (deploy, executions, key synthesis) without the need of a `Process`. It essentially combines
 /// Stack::new() and Stack::init()
-pub fn new_init(program: &Program) -> Result<Stack> {
+pub fn new_init(program: &Program, imports: &[Program]) -> Result<Stack> {
     // Retrieve the program ID.
     let program_id = program.id();
 
@@ -33,6 +33,18 @@ pub fn new_init(program: &Program) -> Result<Stack> {
         verifying_keys: Default::default(),
     };
 
+    // Add the stacks of the imported programs, so the calls to their functions can be resolved.
+    // The imports of those programs are expected to be among the given ones too.
+    for import_id in program.imports().keys() {
+        let import = imports
+            .iter()
+            .find(|import| import.id() == import_id)
+            .ok_or_else(|| anyhow!("imported program {import_id} not found"))?;
+        stack
+            .external_stacks
+            .insert(*import_id, new_init(import, imports)?);
+    }
+
     // Add the program functions to the stack.
     for function in program.functions().values() {
         let name = function.name();
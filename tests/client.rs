@@ -24,13 +24,26 @@ fn basic_program() {
     let (_tempfile, home_path, _) = &new_account();
 
     // deploy a program
-    let (_program_file, program_path, _) = load_program(HELLO_PROGRAM);
+    let (_program_file, program_path, program_id) = load_program(HELLO_PROGRAM);
     let transaction = client_command(home_path, &["program", "deploy", &program_path]).unwrap();
     let transaction_id = get_transaction_id(&transaction).unwrap();
 
     // get deployment tx, need to retry until it gets committed
     retry_command(home_path, &["get", transaction_id]).unwrap();
 
+    // the committed transaction is reported along with its block
+    let status = client_command(home_path, &["tx", "status", transaction_id]).unwrap();
+    assert_eq!("committed", status["status"]);
+    assert!(status["height"].as_u64().unwrap() > 0);
+
+    // the deployment can be found by the id of the program
+    let results = client_command(home_path, &["tx", "search", "--program", &program_id]).unwrap();
+    assert_eq!(transaction_id, results[0]["id"]);
+
+    // unknown transactions are reported as such
+    let status = client_command(home_path, &["tx", "status", &unique_id()]).unwrap();
+    assert_eq!("unknown", status["status"]);
+
     // execute the program, save txid
     let transaction =
         execute_program(home_path, &program_path, "hello", &["1u32", "1u32"]).unwrap();
@@ -47,6 +60,22 @@ fn basic_program() {
 
     // check the output of the execution is the sum of the inputs
     assert_eq!("2u32", value);
+
+    // execute the program setting the transaction to expire, which is included in it
+    let transaction = execute_program(
+        home_path,
+        &program_path,
+        "hello",
+        &["1u32", "1u32", "--expires-in", "100"],
+    )
+    .unwrap();
+    let transaction_id = get_transaction_id(&transaction).unwrap();
+    assert!(transaction
+        .pointer("/Execution/expires_at_height")
+        .unwrap()
+        .as_u64()
+        .is_some());
+    retry_command(home_path, &["get", transaction_id]).unwrap();
 }
 
 #[test]
@@ -57,7 +86,7 @@ fn program_validations() {
     // fail on execute non deployed command
     let error =
         execute_program(home_path, &program_path, HELLO_PROGRAM, &["1u32", "1u32"]).unwrap_err();
-    assert!(error.contains("Error executing transaction 1: Could not verify transaction"));
+    assert!(error.contains("Error executing transaction 7: Could not verify transaction"));
 
     // not fail on dry-running non-deployed program ()
     execute_program(
@@ -92,6 +121,16 @@ fn program_validations() {
     // fail on missing parameter
     let error = execute_program(home_path, &program_path, HELLO_PROGRAM, &["1u32"]).unwrap_err();
     assert!(error.contains("expects 2 inputs"));
+
+    // fail on a transaction that would already be expired
+    let error = execute_program(
+        home_path,
+        &program_id,
+        "hello",
+        &["1u32", "1u32", "--expires-in", "0"],
+    )
+    .unwrap_err();
+    assert!(error.contains("--expires-in"));
 }
 
 #[test]
@@ -315,6 +354,17 @@ fn transfer_credits() {
     // check the the account received the balance
     // (validator balance can't be checked because it could receive a reward while the test is running)
     assert_balance(receiver_home, 10).unwrap();
+
+    // the wallet is already synced, so syncing again shouldn't find new records
+    let output = client_command(receiver_home, &["account", "sync"]).unwrap();
+    assert_eq!(
+        1,
+        output
+            .pointer("/unspent_records")
+            .unwrap()
+            .as_u64()
+            .unwrap()
+    );
 }
 
 #[test]